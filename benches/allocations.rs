@@ -0,0 +1,73 @@
+//! Counts the heap allocations made to scan, compile and run a large generated program. Run
+//! with `cargo bench --bench allocations`.
+
+use codecrafters_interpreter::lox::{Backend, Config, Lox};
+use codecrafters_interpreter::output::Buffer;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// Counts the allocations made on the current thread, and their total size.
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| {
+            let (allocations, bytes) = count.get();
+            count.set((allocations + 1, bytes + layout.size()));
+        });
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// The number of allocations made by `f`, and their total size in bytes.
+fn count(f: impl FnOnce()) -> (usize, usize) {
+    let (allocations, bytes) = ALLOCATIONS.with(Cell::get);
+    f();
+    let (after, after_bytes) = ALLOCATIONS.with(Cell::get);
+    (after - allocations, after_bytes - bytes)
+}
+
+/// A program declaring `copies` classes and functions, each using a few identifiers, numbers,
+/// operators and string literals, and calling them in a loop that builds strings.
+fn program(copies: usize) -> String {
+    let mut source = String::new();
+    for i in 0..copies {
+        source.push_str(&format!(
+            "class Point{i} {{\n  init(x, y) {{ this.x = x; this.y = y; }}\n  \
+             sum() {{ return this.x + this.y * 2.5; }}\n}}\n\
+             fun label{i}(point) {{\n  var name = \"point\";\n  \
+             if (point.sum() >= 10) name = name + \" far\"; else name = name + \" near\";\n  \
+             return name;\n}}\n\
+             for (var i = 0; i < 10; i = i + 1) label{i}(Point{i}(i, {i}));\n"
+        ));
+    }
+    source
+}
+
+fn main() {
+    let source = program(2000);
+    println!("{} KB of source", source.len() / 1024);
+    println!("{:<24}{:>14}{:>14}", "phase", "allocations", "bytes");
+    let report = |phase: &str, (allocations, bytes): (usize, usize)| {
+        println!("{:<24}{:>14}{:>14}", phase, allocations, bytes);
+    };
+
+    report("tokenize", count(|| drop(Lox::new().tokenize(&source))));
+    report("compile", count(|| drop(Lox::new().compile(&source).unwrap())));
+    for backend in [Backend::Interpreter, Backend::Vm] {
+        let config = Config { backend, output: Buffer::new().into(), ..Config::default() };
+        let mut lox = Lox::with_config(config);
+        report(&format!("run ({:?})", backend), count(|| lox.run(&source).unwrap()));
+    }
+}
@@ -0,0 +1,47 @@
+//! Times the tree-walking interpreter against the bytecode VM on a few hot loops. Run with
+//! `cargo bench`; each program is run a few times on each backend and the fastest run is kept.
+
+use codecrafters_interpreter::lox::{Backend, Config, Lox};
+use codecrafters_interpreter::output::Buffer;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+const PROGRAMS: [(&str, &str); 4] = [
+    ("fib", "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\nprint fib(25);"),
+    ("locals", "{ var i = 0; while (i < 2000000) i = i + 1; print i; }"),
+    ("globals", "var sum = 0;\nfor (var i = 0; i < 1000000; i = i + 1) sum = sum + i;\nprint sum;"),
+    (
+        "methods",
+        "class Counter {\n  init() { this.count = 0; }\n  add(n) { this.count = this.count + n; }\n}\n\
+         var counter = Counter();\nfor (var i = 0; i < 300000; i = i + 1) counter.add(i);\n\
+         print counter.count;",
+    ),
+];
+
+/// The fastest of `RUNS` runs of `source` on `backend`, and what it printed.
+fn time(backend: Backend, source: &str) -> (Duration, String) {
+    let mut fastest = Duration::MAX;
+    let mut printed = String::new();
+    for _ in 0..RUNS {
+        let output = Buffer::new();
+        let config = Config { backend, output: output.clone().into(), ..Config::default() };
+        let mut lox = Lox::with_config(config);
+        let start = Instant::now();
+        lox.run(source).expect("benchmarks run without errors");
+        fastest = fastest.min(start.elapsed());
+        printed = output.take();
+    }
+    (fastest, printed)
+}
+
+fn main() {
+    println!("{:<10}{:>14}{:>14}{:>10}", "program", "interpreter", "vm", "speedup");
+    for (name, source) in PROGRAMS {
+        let (interpreter, expected) = time(Backend::Interpreter, source);
+        let (vm, printed) = time(Backend::Vm, source);
+        assert_eq!(printed, expected, "the backends disagree on {}", name);
+        let speedup = interpreter.as_secs_f64() / vm.as_secs_f64();
+        println!("{:<10}{:>14.2?}{:>14.2?}{:>9.1}x", name, interpreter, vm, speedup);
+    }
+}
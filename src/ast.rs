@@ -1,22 +1,78 @@
-use crate::lexer::{Literal, Token};
+use crate::lexer::{Literal, Span, Token};
+use std::cell::Cell;
+use std::rc::Rc;
 
 pub trait Visitor {
     type Output;
-    fn visit(&self, expr: &Expression) -> Self::Output;
+    fn visit(&mut self, expr: &Expression) -> Self::Output;
+}
+
+pub trait StmtVisitor {
+    type Output;
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Self::Output;
 }
 
 type Operator = Token;
-pub type ObjectValue = Literal;
+
+/// The number of scopes between a variable's use and its declaration, filled in by the resolver.
+/// `None` means the variable is global.
+pub type Depth = Cell<Option<usize>>;
 
 pub enum Expression {
+    Assign(Token, Box<Expression>, Depth),
     Binary(Box<Expression>, Operator, Box<Expression>),
-    Grouping(Box<Expression>),
-    Literal(Option<ObjectValue>),
+    Call(Box<Expression>, Token, Vec<Expression>),
+    Get(Box<Expression>, Token),
+    /// The span covers the surrounding parentheses.
+    Grouping(Box<Expression>, Span),
+    Literal(Option<Literal>, Span),
+    Logical(Box<Expression>, Operator, Box<Expression>),
+    Set(Box<Expression>, Token, Box<Expression>),
+    Super(Token, Token, Depth),
+    This(Token, Depth),
     Unary(Operator, Box<Expression>),
+    Variable(Token, Depth),
+}
+
+/// A function declaration, shared between the AST and the function values created from it.
+pub struct Function {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
+}
+
+pub enum Stmt {
+    Block(Vec<Stmt>),
+    Class(Token, Option<Expression>, Vec<Rc<Function>>),
+    Expression(Expression),
+    Function(Rc<Function>),
+    If(Expression, Box<Stmt>, Option<Box<Stmt>>),
+    Print(Expression),
+    Return(Token, Option<Expression>),
+    Var(Token, Option<Expression>),
+    While(Expression, Box<Stmt>),
 }
 
 impl Expression {
-    fn accept(&self, visitor: &impl Visitor<Output = String>) -> String {
+    /// The region of source code this expression was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Assign(name, value, _) => name.span.to(value.span()),
+            Expression::Binary(left, _, right) | Expression::Logical(left, _, right) => {
+                left.span().to(right.span())
+            },
+            Expression::Call(callee, paren, _) => callee.span().to(paren.span),
+            Expression::Get(object, name) => object.span().to(name.span),
+            Expression::Grouping(_, span) | Expression::Literal(_, span) => *span,
+            Expression::Set(object, _, value) => object.span().to(value.span()),
+            Expression::Super(keyword, method, _) => keyword.span.to(method.span),
+            Expression::This(keyword, _) => keyword.span,
+            Expression::Unary(operator, right) => operator.span.to(right.span()),
+            Expression::Variable(name, _) => name.span,
+        }
+    }
+
+    fn accept(&self, visitor: &mut impl Visitor<Output = String>) -> String {
         visitor.visit(self)
     }
 }
@@ -28,7 +84,7 @@ impl AstPrinter {
         Self {}
     }
 
-    pub fn parenthesize(&self, name: &str, exprs: Vec<&Expression>) -> String {
+    pub fn parenthesize(&mut self, name: &str, exprs: Vec<&Expression>) -> String {
         let mut s = format!("({}", name);
         for expr in exprs {
             s.push(' ');
@@ -38,23 +94,45 @@ impl AstPrinter {
         s
     }
 
-    pub fn print(&self, expr: &Expression) -> String {
+    pub fn print(&mut self, expr: &Expression) -> String {
         expr.accept(self)
     }
 }
 
 impl Visitor for AstPrinter {
     type Output = String;
-    fn visit(&self, expr: &Expression) -> Self::Output {
+    fn visit(&mut self, expr: &Expression) -> Self::Output {
         match expr {
+            Expression::Assign(name, value, _) => {
+                self.parenthesize(&format!("= {}", name.lexeme), vec![value.as_ref()])
+            },
             Expression::Binary(left, operator, right) => {
-                self.parenthesize(&operator.lexeme, vec![left.as_ref(), right.as_ref()])
+                self.parenthesize(&operator.lexeme.to_string(), vec![left.as_ref(), right.as_ref()])
+            },
+            Expression::Call(callee, _, arguments) => {
+                let mut exprs = vec![callee.as_ref()];
+                exprs.extend(arguments);
+                self.parenthesize("call", exprs)
+            },
+            Expression::Get(object, name) => {
+                self.parenthesize(&format!(". {}", name.lexeme), vec![object.as_ref()])
             },
-            Expression::Grouping(expr) => self.parenthesize("group", vec![expr.as_ref()]),
-            Expression::Literal(expr) => expr.as_ref().unwrap_or(&Literal::Null).to_string(),
+            Expression::Grouping(expr, _) => self.parenthesize("group", vec![expr.as_ref()]),
+            Expression::Literal(expr, _) => match expr.as_ref().unwrap_or(&Literal::Null) {
+                Literal::Null => "nil".to_string(),
+                literal => literal.to_string(),
+            },
+            Expression::Logical(left, operator, right) => {
+                self.parenthesize(&operator.lexeme.to_string(), vec![left.as_ref(), right.as_ref()])
+            },
+            Expression::Set(object, name, value) => self
+                .parenthesize(&format!("= {}", name.lexeme), vec![object.as_ref(), value.as_ref()]),
+            Expression::Super(_, method, _) => format!("super.{}", method.lexeme),
+            Expression::This(_, _) => "this".to_string(),
             Expression::Unary(operator, expr) => {
-                self.parenthesize(&operator.lexeme, vec![expr.as_ref()])
+                self.parenthesize(&operator.lexeme.to_string(), vec![expr.as_ref()])
             },
+            Expression::Variable(name, _) => name.lexeme.to_string(),
         }
     }
 }
@@ -62,15 +140,25 @@ impl Visitor for AstPrinter {
 #[cfg(test)]
 mod test {
     use crate::ast::{AstPrinter, Expression, Visitor};
-    use crate::lexer::{Literal, Token, TokenType};
+    use crate::lexer::{Literal, Span, Token, TokenType};
 
     #[test]
     fn prints_ast() {
-        let printer = AstPrinter::new();
-        let one = Expression::Literal(Some(Literal::Number(1.0)));
+        let mut printer = AstPrinter::new();
+        let one = Expression::Literal(Some(Literal::Number(1.0)), Span::default());
         let plus = Token::new(TokenType::Plus, "+", None, 1);
-        let two = Expression::Literal(Some(Literal::Number(2.0)));
+        let two = Expression::Literal(Some(Literal::Number(2.0)), Span::default());
         let expr = Expression::Binary(Box::new(one), plus, Box::new(two));
         assert_eq!(printer.visit(&expr), "(+ 1.0 2.0)".to_string())
     }
+
+    #[test]
+    fn spans_cover_whole_expression() {
+        let (tokens, _) = crate::lox::Lox::new().tokenize("1 +\n (2 * x)");
+        let reporter = crate::reporter::Reporter::shared();
+        let expr = crate::parser::Parser::new(&tokens, &reporter).parse();
+        assert_eq!(expr.span(), Span::new(0, 12, 1));
+        let Expression::Binary(_, _, right) = expr else { panic!("expected a binary expression") };
+        assert_eq!(right.span(), Span::new(5, 7, 2));
+    }
 }
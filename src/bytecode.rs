@@ -0,0 +1,534 @@
+use crate::chunk::{Chunk, Constant, OpCode};
+use crate::lexer::Span;
+use crate::object::ObjFunction;
+use crate::symbol::Symbol;
+use bytes::{Buf, BufMut, BytesMut};
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// The first bytes of every compiled Lox file.
+const MAGIC: &[u8; 4] = b"LOXC";
+
+/// Bumped whenever the layout of the file or the instruction set changes.
+pub const FORMAT_VERSION: u16 = 3;
+
+const NUMBER: u8 = 0;
+const STRING: u8 = 1;
+const FUNCTION: u8 = 2;
+
+/// How deeply functions can be nested in a compiled file, so that loading a corrupt file can't
+/// overflow the stack.
+const MAX_NESTING: usize = 256;
+
+/// Why a compiled Lox file could not be loaded.
+#[derive(Debug, PartialEq)]
+pub enum BytecodeError {
+    NotBytecode,
+    UnsupportedVersion(u16),
+    Corrupt(&'static str),
+}
+
+impl Display for BytecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BytecodeError::NotBytecode => write!(f, "Not a compiled Lox file."),
+            BytecodeError::UnsupportedVersion(version) => write!(
+                f,
+                "Compiled with bytecode format version {}, but this interpreter only runs version \
+                 {}. Recompile the script.",
+                version, FORMAT_VERSION
+            ),
+            BytecodeError::Corrupt(reason) => write!(f, "Corrupt compiled Lox file: {}.", reason),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+type BytecodeResult<T> = std::result::Result<T, BytecodeError>;
+
+/// Encodes a compiled script, along with every function declared in it.
+///
+/// The file starts with [`MAGIC`] and the little-endian [`FORMAT_VERSION`], followed by the
+/// script. Each function is written as its name, arity, upvalue count, code, line table and
+/// constants, with nested functions written inline in the constants.
+pub fn serialize(script: &ObjFunction) -> Vec<u8> {
+    let mut buffer = BytesMut::new();
+    buffer.put_slice(MAGIC);
+    buffer.put_u16_le(FORMAT_VERSION);
+    write_function(&mut buffer, script);
+    buffer.to_vec()
+}
+
+fn write_function(buffer: &mut BytesMut, function: &ObjFunction) {
+    write_string(buffer, &function.name);
+    buffer.put_u8(function.arity as u8);
+    buffer.put_u32_le(function.upvalue_count as u32);
+
+    let chunk = &function.chunk;
+    buffer.put_u32_le(chunk.code.len() as u32);
+    buffer.put_slice(&chunk.code);
+
+    // Consecutive bytes nearly always come from the same place, so the line table is stored as
+    // runs of identical lines and spans.
+    let locations: Vec<_> = chunk.lines.iter().zip(&chunk.spans).collect();
+    let runs: Vec<_> = locations.chunk_by(|a, b| a == b).collect();
+    buffer.put_u32_le(runs.len() as u32);
+    for run in runs {
+        let (line, span) = run[0];
+        buffer.put_u32_le(run.len() as u32);
+        buffer.put_u32_le(*line as u32);
+        buffer.put_u32_le(span.offset as u32);
+        buffer.put_u32_le(span.len as u32);
+        buffer.put_u32_le(span.column as u32);
+    }
+
+    buffer.put_u32_le(chunk.constants.len() as u32);
+    for constant in &chunk.constants {
+        match constant {
+            Constant::Number(n) => {
+                buffer.put_u8(NUMBER);
+                buffer.put_f64_le(*n);
+            },
+            Constant::String(s) => {
+                buffer.put_u8(STRING);
+                write_string(buffer, s);
+            },
+            Constant::Function(function) => {
+                buffer.put_u8(FUNCTION);
+                write_function(buffer, function);
+            },
+        }
+    }
+}
+
+fn write_string(buffer: &mut BytesMut, s: &str) {
+    buffer.put_u32_le(s.len() as u32);
+    buffer.put_slice(s.as_bytes());
+}
+
+/// Decodes a script written by [`serialize`], checking that the VM can run its instructions
+/// without reading outside of its code, constants, stack or upvalues.
+pub fn deserialize(mut bytes: &[u8]) -> BytecodeResult<Rc<ObjFunction>> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(BytecodeError::NotBytecode);
+    }
+    bytes.advance(MAGIC.len());
+    let mut reader = Reader { bytes, depth: 0 };
+    let version = reader.u16()?;
+    if version != FORMAT_VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+    let script = reader.function()?;
+    if script.arity != 0 || script.upvalue_count != 0 {
+        return Err(BytecodeError::Corrupt("invalid script"));
+    }
+    if reader.bytes.has_remaining() {
+        return Err(BytecodeError::Corrupt("trailing bytes after the script"));
+    }
+    Ok(Rc::new(script))
+}
+
+/// Reads values off the front of a byte slice, failing instead of panicking at its end.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    /// How many functions enclose the one being read.
+    depth: usize,
+}
+
+impl Reader<'_> {
+    fn ensure(&self, len: usize) -> BytecodeResult<()> {
+        if self.bytes.remaining() < len {
+            return Err(BytecodeError::Corrupt("unexpected end of file"));
+        }
+        Ok(())
+    }
+
+    fn u8(&mut self) -> BytecodeResult<u8> {
+        self.ensure(1)?;
+        Ok(self.bytes.get_u8())
+    }
+
+    fn u16(&mut self) -> BytecodeResult<u16> {
+        self.ensure(2)?;
+        Ok(self.bytes.get_u16_le())
+    }
+
+    fn u32(&mut self) -> BytecodeResult<usize> {
+        self.ensure(4)?;
+        Ok(self.bytes.get_u32_le() as usize)
+    }
+
+    fn f64(&mut self) -> BytecodeResult<f64> {
+        self.ensure(8)?;
+        Ok(self.bytes.get_f64_le())
+    }
+
+    fn slice(&mut self, len: usize) -> BytecodeResult<&[u8]> {
+        self.ensure(len)?;
+        let (slice, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(slice)
+    }
+
+    fn string(&mut self) -> BytecodeResult<String> {
+        let len = self.u32()?;
+        let bytes = self.slice(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| BytecodeError::Corrupt("invalid string"))
+    }
+
+    fn function(&mut self) -> BytecodeResult<ObjFunction> {
+        let name = self.string()?;
+        let arity = self.u8()? as usize;
+        let upvalue_count = self.u32()?;
+
+        let len = self.u32()?;
+        let mut chunk = Chunk { code: self.slice(len)?.to_vec(), ..Chunk::default() };
+        for _ in 0..self.u32()? {
+            let count = self.u32()?;
+            let line = self.u32()?;
+            let span = Span::new(self.u32()?, self.u32()?, self.u32()?);
+            if chunk.lines.len() + count > chunk.code.len() {
+                return Err(BytecodeError::Corrupt("line table longer than the code"));
+            }
+            chunk.lines.extend(std::iter::repeat(line).take(count));
+            chunk.spans.extend(std::iter::repeat(span).take(count));
+        }
+        if chunk.lines.len() != chunk.code.len() {
+            return Err(BytecodeError::Corrupt("line table shorter than the code"));
+        }
+
+        for _ in 0..self.u32()? {
+            let constant = match self.u8()? {
+                NUMBER => Constant::Number(self.f64()?),
+                STRING => Constant::String(Symbol::intern(&self.string()?)),
+                FUNCTION if self.depth == MAX_NESTING => {
+                    return Err(BytecodeError::Corrupt("functions nested too deeply"));
+                },
+                FUNCTION => {
+                    self.depth += 1;
+                    let function = self.function();
+                    self.depth -= 1;
+                    Constant::Function(Rc::new(function?))
+                },
+                _ => return Err(BytecodeError::Corrupt("unknown constant type")),
+            };
+            chunk.constants.push(constant);
+        }
+
+        let function = ObjFunction { name, arity, upvalue_count, chunk };
+        verify(&function)?;
+        Ok(function)
+    }
+}
+
+/// Checks that every instruction the function can run is known, that its operands stay within
+/// the code, the constant pool, the stack and the function's upvalues, and that the code can't
+/// run past its end.
+///
+/// Follows every path through the code, tracking the slots on the stack and which of them a
+/// closure captured. Each instruction must see the same slots whichever path reaches it, and a
+/// captured slot can only be popped by closing its upvalue.
+fn verify(function: &ObjFunction) -> BytecodeResult<()> {
+    let chunk = &function.chunk;
+    let operand = |offset: usize| {
+        chunk.code.get(offset).copied().ok_or(BytecodeError::Corrupt("truncated instruction"))
+    };
+    // Index operands are three bytes long after a `Wide` prefix.
+    let index = |offset: usize, wide: bool| -> BytecodeResult<usize> {
+        if wide {
+            let bytes = [0, operand(offset)?, operand(offset + 1)?, operand(offset + 2)?];
+            Ok(u32::from_be_bytes(bytes) as usize)
+        } else {
+            Ok(operand(offset)? as usize)
+        }
+    };
+    let constant = |offset: usize, wide: bool| -> BytecodeResult<&Constant> {
+        chunk.constants.get(index(offset, wide)?).ok_or(BytecodeError::Corrupt("unknown constant"))
+    };
+    let upvalue = |index: usize| {
+        if index >= function.upvalue_count {
+            return Err(BytecodeError::Corrupt("unknown upvalue"));
+        }
+        Ok(())
+    };
+
+    // The stack slots at each instruction reached so far, flagged when captured by a closure.
+    let mut reached: Vec<Option<Vec<bool>>> = vec![None; chunk.code.len()];
+    // The callee and its arguments are on the stack when the function starts.
+    let mut pending = vec![(0, vec![false; function.arity + 1])];
+    while let Some((start, mut stack)) = pending.pop() {
+        match reached.get(start) {
+            None => return Err(BytecodeError::Corrupt("missing return")),
+            Some(Some(slots)) if *slots == stack => continue,
+            Some(Some(_)) => return Err(BytecodeError::Corrupt("inconsistent stack")),
+            Some(None) => reached[start] = Some(stack.clone()),
+        }
+
+        let decode = |byte| {
+            OpCode::try_from(byte).map_err(|_| BytecodeError::Corrupt("unknown instruction"))
+        };
+        let mut offset = start;
+        let mut op = decode(chunk.code[offset])?;
+        let wide = op == OpCode::Wide;
+        if wide {
+            offset += 1;
+            op = decode(operand(offset)?)?;
+            if !op.is_widenable() {
+                return Err(BytecodeError::Corrupt("invalid wide instruction"));
+            }
+        }
+        let width = if wide { 3 } else { 1 };
+        let local = |index: usize, stack: &[bool]| {
+            if index >= stack.len() {
+                return Err(BytecodeError::Corrupt("unknown local"));
+            }
+            Ok(())
+        };
+        let name = |offset: usize| match constant(offset, wide)? {
+            Constant::String(_) => Ok(()),
+            _ => Err(BytecodeError::Corrupt("invalid name")),
+        };
+
+        // The length of the instruction, how many values it pops and how many it then pushes.
+        let (len, pops, pushes) = match op {
+            OpCode::Constant => match constant(offset + 1, wide)? {
+                Constant::Function(_) => return Err(BytecodeError::Corrupt("invalid constant")),
+                _ => (1 + width, 0, 1),
+            },
+            OpCode::Nil | OpCode::True | OpCode::False => (1, 0, 1),
+            OpCode::Pop | OpCode::Print | OpCode::CloseUpvalue => (1, 1, 0),
+            OpCode::GetLocal => {
+                local(index(offset + 1, wide)?, &stack)?;
+                (1 + width, 0, 1)
+            },
+            OpCode::SetLocal => {
+                local(index(offset + 1, wide)?, &stack)?;
+                (1 + width, 1, 1)
+            },
+            OpCode::GetUpvalue => {
+                upvalue(index(offset + 1, wide)?)?;
+                (1 + width, 0, 1)
+            },
+            OpCode::SetUpvalue => {
+                upvalue(index(offset + 1, wide)?)?;
+                (1 + width, 1, 1)
+            },
+            OpCode::GetGlobal | OpCode::Class => {
+                name(offset + 1)?;
+                (1 + width, 0, 1)
+            },
+            OpCode::DefineGlobal => {
+                name(offset + 1)?;
+                (1 + width, 1, 0)
+            },
+            OpCode::SetGlobal | OpCode::GetProperty => {
+                name(offset + 1)?;
+                (1 + width, 1, 1)
+            },
+            OpCode::SetProperty | OpCode::GetSuper => {
+                name(offset + 1)?;
+                (1 + width, 2, 1)
+            },
+            // Pops the method or subclass, leaving the class below it on the stack.
+            OpCode::Method | OpCode::Inherit => {
+                if stack.len() < 2 {
+                    return Err(BytecodeError::Corrupt("stack underflow"));
+                }
+                if op == OpCode::Method {
+                    name(offset + 1)?;
+                    (1 + width, 1, 0)
+                } else {
+                    (1, 1, 0)
+                }
+            },
+            OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide => (1, 2, 1),
+            OpCode::Not | OpCode::Negate | OpCode::Return => (1, 1, 1),
+            OpCode::Jump | OpCode::Loop => (3, 0, 0),
+            OpCode::JumpIfFalse => (3, 1, 1),
+            OpCode::Call => (1 + width, index(offset + 1, wide)? + 1, 1),
+            OpCode::Closure => match constant(offset + 1, wide)? {
+                Constant::Function(closure) => {
+                    let mut capture = offset + 1 + width;
+                    for _ in 0..closure.upvalue_count {
+                        let index = index(capture + 1, wide)?;
+                        match operand(capture)? {
+                            0 => upvalue(index)?,
+                            1 => {
+                                local(index, &stack)?;
+                                stack[index] = true;
+                            },
+                            _ => return Err(BytecodeError::Corrupt("invalid closure")),
+                        }
+                        capture += 1 + width;
+                    }
+                    (capture - offset, 0, 1)
+                },
+                _ => return Err(BytecodeError::Corrupt("invalid closure")),
+            },
+            OpCode::Wide => return Err(BytecodeError::Corrupt("invalid wide instruction")),
+        };
+
+        if pops > stack.len() {
+            return Err(BytecodeError::Corrupt("stack underflow"));
+        }
+        // Closing an upvalue moves the captured variable off the stack before popping it, while
+        // returning pops the result before closing the function's upvalues.
+        let popped = stack.split_off(stack.len() - pops);
+        if op != OpCode::CloseUpvalue && popped.contains(&true) {
+            return Err(BytecodeError::Corrupt("captured variable popped"));
+        }
+        stack.extend(std::iter::repeat(false).take(pushes));
+
+        let next = offset + len;
+        match op {
+            OpCode::Return => {},
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+                let jump = u16::from_be_bytes([operand(offset + 1)?, operand(offset + 2)?]);
+                let target = match op {
+                    OpCode::Loop => next.checked_sub(jump as usize),
+                    _ => Some(next + jump as usize),
+                };
+                match target {
+                    Some(target) if target <= chunk.code.len() => {
+                        if op == OpCode::JumpIfFalse {
+                            pending.push((next, stack.clone()));
+                        }
+                        pending.push((target, stack));
+                    },
+                    _ => return Err(BytecodeError::Corrupt("jump out of the code")),
+                }
+            },
+            _ => pending.push((next, stack)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bytecode::{deserialize, serialize, BytecodeError};
+    use crate::chunk::{Constant, OpCode};
+    use crate::disassembler::disassemble;
+    use crate::object::ObjFunction;
+    use crate::output::Buffer;
+    use crate::test_support;
+    use crate::vm::Vm;
+
+    fn compile(source: &str) -> Vec<u8> {
+        serialize(&test_support::compile(source))
+    }
+
+    #[test]
+    fn round_trips_functions_constants_and_lines() {
+        let source = "fun f(a) {\n  var b = \"x\";\n  fun g() { return a + b; }\n  return g;\n}\n\
+                      class C < D { m() { return super.m(1.5); } }\nprint f(1)();";
+        let script = test_support::compile(source);
+
+        let loaded = deserialize(&serialize(&script)).unwrap();
+        assert_eq!(disassemble(&loaded), disassemble(&script));
+        assert_eq!(loaded.chunk.spans, script.chunk.spans);
+    }
+
+    #[test]
+    fn rejects_other_files_and_versions() {
+        assert_eq!(deserialize(b"print 1;").err(), Some(BytecodeError::NotBytecode));
+
+        let mut bytes = compile("print 1;");
+        bytes[4] = 99;
+        let error = deserialize(&bytes).err().unwrap();
+        assert_eq!(error, BytecodeError::UnsupportedVersion(99));
+        assert_eq!(
+            error.to_string(),
+            "Compiled with bytecode format version 99, but this interpreter only runs version 3. \
+             Recompile the script."
+        );
+    }
+
+    #[test]
+    fn rejects_corrupt_files() {
+        let bytes = compile("var a = 1;\nprint a;");
+        for len in 6..bytes.len() {
+            assert!(matches!(deserialize(&bytes[..len]), Err(BytecodeError::Corrupt(_))));
+        }
+
+        // Point the first instruction's operand past the end of the constant pool.
+        let mut bytes = bytes;
+        let code = 4 + 2 + 4 + 1 + 4 + 4;
+        bytes[code + 1] = 42;
+        assert_eq!(deserialize(&bytes).err(), Some(BytecodeError::Corrupt("unknown constant")));
+        bytes[code + 1] = 0;
+
+        // The code is `Constant 0, DefineGlobal 1, GetGlobal 1, Print, Nil, Return`.
+        let corrupt = |at: usize, op: OpCode| {
+            let mut bytes = bytes.clone();
+            bytes[code + at] = op as u8;
+            deserialize(&bytes).err()
+        };
+        assert_eq!(corrupt(4, OpCode::Add), Some(BytecodeError::Corrupt("stack underflow")));
+        assert_eq!(corrupt(4, OpCode::GetLocal), Some(BytecodeError::Corrupt("unknown local")));
+        assert_eq!(corrupt(4, OpCode::GetUpvalue), Some(BytecodeError::Corrupt("unknown upvalue")));
+        assert_eq!(corrupt(8, OpCode::Pop), Some(BytecodeError::Corrupt("missing return")));
+    }
+
+    /// Whether `function` or a function nested in it contains a byte that could be decoded as a
+    /// backward jump.
+    fn could_loop(function: &ObjFunction) -> bool {
+        function.chunk.code.contains(&(OpCode::Loop as u8))
+            || function.chunk.constants.iter().any(|constant| match constant {
+                Constant::Function(function) => could_loop(function),
+                _ => false,
+            })
+    }
+
+    #[test]
+    fn rejects_or_safely_runs_mutated_files() {
+        let source = "class A {\n  init(x) { this.x = x; }\n  get() { return this.x; }\n}\n\
+                      class B < A { get() { return super.get() + 1; } }\n\
+                      fun outer(a) {\n  var b = a * 2;\n  fun inner() { return a + b; }\n  \
+                      return inner;\n}\nprint B(1).get();\nprint outer(2)();\n\
+                      { var s = \"s\"; fun f() { return s + \"t\"; } print f(); }";
+        let bytes = compile(source);
+        // A fixed xorshift sequence, so that a failure can be reproduced.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+        let mut ran = 0;
+        for _ in 0..5000 {
+            let mut mutant = bytes.clone();
+            for _ in 0..2 {
+                let at = 6 + random(mutant.len() - 6);
+                mutant[at] = random(256) as u8;
+            }
+            let script = match deserialize(&mutant) {
+                Ok(script) => script,
+                Err(error) => {
+                    assert!(matches!(error, BytecodeError::Corrupt(_)));
+                    continue;
+                },
+            };
+            // The program has no loops, so a mutant without one can't run forever.
+            if could_loop(&script) {
+                continue;
+            }
+            let mut vm = Vm::new();
+            vm.set_output(Buffer::new().into());
+            let _ = vm.interpret(script);
+            ran += 1;
+        }
+        assert!(ran > 1000, "only {} mutants ran", ran);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::ast::Function;
+use crate::class::LoxInstance;
+use crate::interpreter::{Completion, Environment, Interpreter, RuntimeResult};
+use crate::lexer::Token;
+use crate::native::NativeFunction;
+use crate::symbol::Symbol;
+use crate::value::Value;
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// Anything that can be invoked with `callee(arguments)` from Lox code.
+pub trait LoxCallable: Display {
+    /// The number of arguments the callable expects.
+    fn arity(&self) -> usize;
+
+    /// Invokes the callable. `arguments` has already been checked against [`LoxCallable::arity`],
+    /// and `paren` is the closing parenthesis of the call, to report errors at.
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Value>,
+    ) -> RuntimeResult<Value>;
+
+    /// The callable as a native function, which the bytecode VM can call too.
+    fn as_native(&self) -> Option<&NativeFunction> {
+        None
+    }
+}
+
+/// A function declared in Lox code with `fun`, closing over the environment it was declared in.
+pub struct LoxFunction {
+    declaration: Rc<Function>,
+    closure: Rc<RefCell<Environment>>,
+    is_initializer: bool,
+}
+
+impl LoxFunction {
+    pub fn new(
+        declaration: Rc<Function>,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+    ) -> Self {
+        Self { declaration, closure, is_initializer }
+    }
+
+    /// Creates a copy of this method whose `this` refers to `instance`.
+    pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> LoxFunction {
+        let mut environment = Environment::new(Some(self.closure.clone()));
+        environment.define(Symbol::this(), Value::Instance(instance));
+        LoxFunction::new(
+            self.declaration.clone(),
+            Rc::new(RefCell::new(environment)),
+            self.is_initializer,
+        )
+    }
+}
+
+impl LoxCallable for LoxFunction {
+    fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Value>,
+    ) -> RuntimeResult<Value> {
+        let mut environment = Environment::new(Some(self.closure.clone()));
+        for (param, argument) in self.declaration.params.iter().zip(arguments) {
+            environment.define(param.symbol().clone(), argument);
+        }
+        let completion = interpreter.execute_call(&self.declaration.body, environment, paren)?;
+        // Initializers always hand back the instance, even on an early `return;`.
+        if self.is_initializer {
+            let this = self.closure.borrow().get_at(0, &Symbol::this());
+            return Ok(this.expect("initializers are only called once bound to an instance"));
+        }
+        match completion {
+            Completion::Break(value) => Ok(value),
+            Completion::Continue(()) => Ok(Value::Nil),
+        }
+    }
+}
+
+impl Display for LoxFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.declaration.name.lexeme)
+    }
+}
@@ -0,0 +1,256 @@
+use crate::lexer::Span;
+use crate::object::ObjFunction;
+use crate::symbol::Symbol;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// An instruction of the bytecode VM. Operands follow the opcode: a single byte for constant,
+/// slot and argument count operands, and two bytes, big-endian, for jump offsets.
+///
+/// Constant, slot and argument count operands are three bytes, big-endian, after a
+/// [`OpCode::Wide`] prefix, for chunks with more than 256 constants or locals.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum OpCode {
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal,
+    SetLocal,
+    GetGlobal,
+    DefineGlobal,
+    SetGlobal,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    SetProperty,
+    GetSuper,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    /// Followed by the function constant, then an `(is_local, index)` pair for each variable the
+    /// closure captures, where only the index is widened by [`OpCode::Wide`].
+    Closure,
+    CloseUpvalue,
+    Return,
+    Class,
+    Inherit,
+    Method,
+    /// Widens the operands of the next instruction, see [`OpCode`].
+    Wide,
+}
+
+impl OpCode {
+    /// Every opcode, indexed by its byte value.
+    const ALL: [OpCode; 39] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Inherit,
+        OpCode::Method,
+        OpCode::Wide,
+    ];
+
+    /// Whether the operands of the instruction can be widened by [`OpCode::Wide`].
+    pub fn is_widenable(self) -> bool {
+        matches!(
+            self,
+            OpCode::Constant
+                | OpCode::GetLocal
+                | OpCode::SetLocal
+                | OpCode::GetGlobal
+                | OpCode::DefineGlobal
+                | OpCode::SetGlobal
+                | OpCode::GetUpvalue
+                | OpCode::SetUpvalue
+                | OpCode::GetProperty
+                | OpCode::SetProperty
+                | OpCode::GetSuper
+                | OpCode::Call
+                | OpCode::Closure
+                | OpCode::Class
+                | OpCode::Method
+        )
+    }
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        OpCode::ALL.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+impl Display for OpCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let as_str = match self {
+            OpCode::Constant => "OP_CONSTANT",
+            OpCode::Nil => "OP_NIL",
+            OpCode::True => "OP_TRUE",
+            OpCode::False => "OP_FALSE",
+            OpCode::Pop => "OP_POP",
+            OpCode::GetLocal => "OP_GET_LOCAL",
+            OpCode::SetLocal => "OP_SET_LOCAL",
+            OpCode::GetGlobal => "OP_GET_GLOBAL",
+            OpCode::DefineGlobal => "OP_DEFINE_GLOBAL",
+            OpCode::SetGlobal => "OP_SET_GLOBAL",
+            OpCode::GetUpvalue => "OP_GET_UPVALUE",
+            OpCode::SetUpvalue => "OP_SET_UPVALUE",
+            OpCode::GetProperty => "OP_GET_PROPERTY",
+            OpCode::SetProperty => "OP_SET_PROPERTY",
+            OpCode::GetSuper => "OP_GET_SUPER",
+            OpCode::Equal => "OP_EQUAL",
+            OpCode::NotEqual => "OP_NOT_EQUAL",
+            OpCode::Greater => "OP_GREATER",
+            OpCode::GreaterEqual => "OP_GREATER_EQUAL",
+            OpCode::Less => "OP_LESS",
+            OpCode::LessEqual => "OP_LESS_EQUAL",
+            OpCode::Add => "OP_ADD",
+            OpCode::Subtract => "OP_SUBTRACT",
+            OpCode::Multiply => "OP_MULTIPLY",
+            OpCode::Divide => "OP_DIVIDE",
+            OpCode::Not => "OP_NOT",
+            OpCode::Negate => "OP_NEGATE",
+            OpCode::Print => "OP_PRINT",
+            OpCode::Jump => "OP_JUMP",
+            OpCode::JumpIfFalse => "OP_JUMP_IF_FALSE",
+            OpCode::Loop => "OP_LOOP",
+            OpCode::Call => "OP_CALL",
+            OpCode::Closure => "OP_CLOSURE",
+            OpCode::CloseUpvalue => "OP_CLOSE_UPVALUE",
+            OpCode::Return => "OP_RETURN",
+            OpCode::Class => "OP_CLASS",
+            OpCode::Inherit => "OP_INHERIT",
+            OpCode::Method => "OP_METHOD",
+            OpCode::Wide => "OP_WIDE",
+        };
+        write!(f, "{}", as_str)
+    }
+}
+
+/// A value known at compile time, stored in a chunk's constant pool.
+#[derive(Clone)]
+pub enum Constant {
+    Number(f64),
+    /// A string literal, or the name of a variable or property.
+    String(Symbol),
+    Function(Rc<ObjFunction>),
+}
+
+impl Display for Constant {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Constant::Number(n) => write!(f, "{}", n),
+            Constant::String(s) => write!(f, "{}", s),
+            Constant::Function(function) => write!(f, "{}", function),
+        }
+    }
+}
+
+/// The bytecode of a single function.
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Constant>,
+    /// The source line of each byte of `code`, to report runtime errors at.
+    pub lines: Vec<usize>,
+    /// The source code each byte of `code` was compiled from.
+    pub spans: Vec<Span>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, byte: u8, line: usize, span: Span) {
+        self.code.push(byte);
+        self.lines.push(line);
+        self.spans.push(span);
+    }
+
+    /// Adds `constant` to the pool, reusing an equal number or string already there, and returns
+    /// its index.
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        let existing = self.constants.iter().position(|candidate| match (candidate, &constant) {
+            (Constant::Number(a), Constant::Number(b)) => a.to_bits() == b.to_bits(),
+            (Constant::String(a), Constant::String(b)) => a == b,
+            _ => false,
+        });
+        existing.unwrap_or_else(|| {
+            self.constants.push(constant);
+            self.constants.len() - 1
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunk::{Chunk, Constant, OpCode};
+    use crate::symbol::Symbol;
+
+    #[test]
+    fn decodes_every_opcode() {
+        for (byte, op) in OpCode::ALL.iter().enumerate() {
+            assert_eq!(*op as u8, byte as u8);
+            assert_eq!(OpCode::try_from(byte as u8), Ok(*op));
+        }
+        assert_eq!(OpCode::try_from(OpCode::ALL.len() as u8), Err(OpCode::ALL.len() as u8));
+    }
+
+    #[test]
+    fn reuses_constants() {
+        let mut chunk = Chunk::default();
+        assert_eq!(chunk.add_constant(Constant::Number(1.0)), 0);
+        assert_eq!(chunk.add_constant(Constant::String(Symbol::intern("a"))), 1);
+        assert_eq!(chunk.add_constant(Constant::Number(1.0)), 0);
+        assert_eq!(chunk.add_constant(Constant::String(Symbol::intern("a"))), 1);
+        assert_eq!(chunk.add_constant(Constant::Number(-0.0)), 2);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::callable::{LoxCallable, LoxFunction};
+use crate::interpreter::{Interpreter, RuntimeResult};
+use crate::lexer::Token;
+use crate::symbol::{Symbol, SymbolMap};
+use crate::value::Value;
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// A class declared with `class`. Calling it creates a new instance.
+pub struct LoxClass {
+    pub name: String,
+    superclass: Option<Rc<LoxClass>>,
+    methods: SymbolMap<Rc<LoxFunction>>,
+}
+
+impl LoxClass {
+    pub fn new(
+        name: &str,
+        superclass: Option<Rc<LoxClass>>,
+        methods: SymbolMap<Rc<LoxFunction>>,
+    ) -> Self {
+        Self { name: name.to_string(), superclass, methods }
+    }
+
+    /// Looks up a method on this class, then up the superclass chain.
+    pub fn find_method(&self, name: &Symbol) -> Option<Rc<LoxFunction>> {
+        match self.methods.get(name) {
+            Some(method) => Some(method.clone()),
+            None => self.superclass.as_ref()?.find_method(name),
+        }
+    }
+}
+
+impl LoxCallable for LoxClass {
+    fn arity(&self) -> usize {
+        self.find_method(&Symbol::init()).map_or(0, |initializer| initializer.arity())
+    }
+
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Value>,
+    ) -> RuntimeResult<Value> {
+        let instance = Rc::new(RefCell::new(LoxInstance::new(self.clone())));
+        if let Some(initializer) = self.find_method(&Symbol::init()) {
+            Rc::new(initializer.bind(instance.clone())).call(interpreter, paren, arguments)?;
+        }
+        Ok(Value::Instance(instance))
+    }
+}
+
+impl Display for LoxClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// An object created by calling a [`LoxClass`], holding its own fields.
+pub struct LoxInstance {
+    class: Rc<LoxClass>,
+    fields: SymbolMap<Value>,
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> Self {
+        Self { class, fields: SymbolMap::default() }
+    }
+
+    /// Looks up a property on `instance`. Fields shadow methods, which are bound to `instance`.
+    pub fn get(instance: &Rc<RefCell<LoxInstance>>, name: &Token) -> Option<Value> {
+        if let Some(value) = instance.borrow().fields.get(name.symbol()) {
+            return Some(value.clone());
+        }
+        let method = instance.borrow().class.find_method(name.symbol())?;
+        Some(Value::Callable(Rc::new(method.bind(instance.clone()))))
+    }
+
+    pub fn set(&mut self, name: &Token, value: Value) {
+        self.fields.insert(name.symbol().clone(), value);
+    }
+}
+
+impl Display for LoxInstance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
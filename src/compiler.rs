@@ -0,0 +1,570 @@
+use crate::ast::{Expression, Function, Stmt, StmtVisitor, Visitor};
+use crate::chunk::{Chunk, Constant, OpCode};
+use crate::diagnostic::{Diagnostic, Phase};
+use crate::lexer::{Lexeme, Literal, Span, Token, TokenType};
+use crate::object::ObjFunction;
+use crate::reporter::SharedReporter;
+use crate::symbol::Symbol;
+use std::rc::Rc;
+
+/// Operands indexing locals, upvalues and constants are a single byte, or three bytes after an
+/// [`OpCode::Wide`] prefix.
+const MAX_OPERAND: usize = (1 << 24) - 1;
+
+#[derive(Copy, Clone, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: Symbol,
+    depth: usize,
+    /// Whether a closure captures the variable, which must then be moved off the stack when it
+    /// goes out of scope.
+    is_captured: bool,
+}
+
+/// A variable captured by the function being compiled: either a local of the enclosing function,
+/// or one of the enclosing function's own upvalues.
+#[derive(Copy, Clone, PartialEq)]
+struct UpvalueRef {
+    index: usize,
+    is_local: bool,
+}
+
+/// The function being compiled, and the variables in scope at the current point of its body.
+struct FunctionState {
+    function: ObjFunction,
+    kind: FunctionKind,
+    /// Locals in stack slot order. Slot 0 holds the function itself, or `this` in methods.
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+}
+
+impl FunctionState {
+    fn new(name: &str, kind: FunctionKind) -> Self {
+        let receiver = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => Symbol::this(),
+            _ => Symbol::intern(""),
+        };
+        Self {
+            function: ObjFunction { name: name.to_string(), ..ObjFunction::default() },
+            kind,
+            locals: vec![Local { name: receiver, depth: 0, is_captured: false }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+        }
+    }
+}
+
+/// Compiles a resolved program to bytecode for the [`Vm`](crate::vm::Vm), in a single pass over
+/// the syntax tree.
+///
+/// The resolver has already rejected invalid programs, so the only errors left to report are
+/// the limits of the bytecode format, such as the number of constants in a chunk.
+pub struct Compiler {
+    /// The function being compiled, innermost last.
+    states: Vec<FunctionState>,
+    /// Where the code being emitted came from, to report runtime errors at.
+    line: usize,
+    span: Span,
+    /// The offset of the first character of each line of the source code, to find the line of
+    /// literals, which don't keep their token.
+    line_starts: Vec<usize>,
+    had_error: bool,
+    reporter: SharedReporter,
+}
+
+impl Compiler {
+    pub fn new(reporter: &SharedReporter) -> Self {
+        let line_starts = match reporter.borrow().source() {
+            Some(source) => {
+                let starts = source.match_indices('\n').map(|(index, _)| index + 1);
+                std::iter::once(0).chain(starts).collect()
+            },
+            None => Vec::new(),
+        };
+        Self {
+            states: vec![FunctionState::new("", FunctionKind::Script)],
+            line: 1,
+            span: Span::default(),
+            line_starts,
+            had_error: false,
+            reporter: reporter.clone(),
+        }
+    }
+
+    /// Compiles a program into the function for its top-level code.
+    pub fn compile(mut self, statements: &[Stmt]) -> Rc<ObjFunction> {
+        for stmt in statements {
+            self.visit_stmt(stmt);
+        }
+        self.emit_return();
+        Rc::new(self.states.pop().expect("the script is always being compiled").function)
+    }
+
+    /// Compiles a single expression into a function returning its value.
+    pub fn compile_expression(mut self, expr: &Expression) -> Rc<ObjFunction> {
+        self.visit(expr);
+        self.emit(OpCode::Return);
+        Rc::new(self.states.pop().expect("the script is always being compiled").function)
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.states.last_mut().expect("a function is always being compiled")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().function.chunk
+    }
+
+    /// Attributes the code emitted from now on to `token`.
+    fn at(&mut self, token: &Token) {
+        self.at_span(token, token.span);
+    }
+
+    /// Attributes the code emitted from now on to `token`, blaming `span` for runtime errors.
+    fn at_span(&mut self, token: &Token, span: Span) {
+        self.line = token.line;
+        self.span = span;
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let (line, span) = (self.line, self.span);
+        self.chunk().write(byte, line, span);
+    }
+
+    fn emit(&mut self, op: OpCode) {
+        self.emit_byte(op as u8);
+    }
+
+    /// Emits `op` with an index operand, prefixed by [`OpCode::Wide`] if it doesn't fit a byte.
+    fn emit_with(&mut self, op: OpCode, operand: usize) {
+        let wide = operand > u8::MAX as usize;
+        if wide {
+            self.emit(OpCode::Wide);
+        }
+        self.emit(op);
+        self.emit_operand(operand, wide);
+    }
+
+    fn emit_operand(&mut self, operand: usize, wide: bool) {
+        if wide {
+            for &byte in &(operand as u32).to_be_bytes()[1..] {
+                self.emit_byte(byte);
+            }
+        } else {
+            self.emit_byte(operand as u8);
+        }
+    }
+
+    /// Returns from the current function: initializers return the instance, other functions nil.
+    fn emit_return(&mut self) {
+        match self.state().kind {
+            FunctionKind::Initializer => self.emit_with(OpCode::GetLocal, 0),
+            _ => self.emit(OpCode::Nil),
+        }
+        self.emit(OpCode::Return);
+    }
+
+    /// Emits a jump with a placeholder offset, returning where to patch it.
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit(op);
+        self.emit_byte(0xff);
+        self.emit_byte(0xff);
+        self.chunk().code.len() - 2
+    }
+
+    /// Points the jump at `offset` to the next instruction.
+    fn patch_jump(&mut self, offset: usize) {
+        let jump = self.chunk().code.len() - offset - 2;
+        let Ok(jump) = u16::try_from(jump) else {
+            return self.error("E0403", "Too much code to jump over.");
+        };
+        self.chunk().code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
+    }
+
+    fn emit_loop(&mut self, start: usize) {
+        self.emit(OpCode::Loop);
+        let offset = self.chunk().code.len() - start + 2;
+        let offset = u16::try_from(offset).unwrap_or_else(|_| {
+            self.error("E0404", "Loop body too large.");
+            0
+        });
+        for byte in offset.to_be_bytes() {
+            self.emit_byte(byte);
+        }
+    }
+
+    fn make_constant(&mut self, constant: Constant) -> usize {
+        let index = self.chunk().add_constant(constant);
+        if index > MAX_OPERAND {
+            self.error("E0400", "Too many constants in one chunk.");
+            return 0;
+        }
+        index
+    }
+
+    fn identifier_constant(&mut self, name: &Symbol) -> usize {
+        self.make_constant(Constant::String(name.clone()))
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    /// Discards the locals of the innermost scope, moving those captured by closures to the heap.
+    fn end_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        while let Some(local) = self.state().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            let op = if local.is_captured { OpCode::CloseUpvalue } else { OpCode::Pop };
+            self.state().locals.pop();
+            self.emit(op);
+        }
+    }
+
+    /// Declares a local for the value on top of the stack.
+    fn add_local(&mut self, name: Symbol) {
+        if self.state().locals.len() > MAX_OPERAND {
+            return self.error("E0401", "Too many local variables in function.");
+        }
+        let depth = self.state().scope_depth;
+        self.state().locals.push(Local { name, depth, is_captured: false });
+    }
+
+    /// Binds the value on top of the stack to `name`, as a local inside a scope and as a global
+    /// otherwise.
+    fn define_variable(&mut self, name: &Token) {
+        if self.state().scope_depth > 0 {
+            self.add_local(name.symbol().clone());
+        } else {
+            let constant = self.identifier_constant(name.symbol());
+            self.emit_with(OpCode::DefineGlobal, constant);
+        }
+    }
+
+    fn resolve_local(&self, state: usize, name: &Symbol) -> Option<usize> {
+        self.states[state].locals.iter().rposition(|local| local.name == *name)
+    }
+
+    /// Finds `name` in the functions enclosing `state`, capturing it in each function in between.
+    fn resolve_upvalue(&mut self, state: usize, name: &Symbol) -> Option<usize> {
+        let enclosing = state.checked_sub(1)?;
+        if let Some(slot) = self.resolve_local(enclosing, name) {
+            self.states[enclosing].locals[slot].is_captured = true;
+            return self.add_upvalue(state, UpvalueRef { index: slot, is_local: true });
+        }
+        let index = self.resolve_upvalue(enclosing, name)?;
+        self.add_upvalue(state, UpvalueRef { index, is_local: false })
+    }
+
+    fn add_upvalue(&mut self, state: usize, upvalue: UpvalueRef) -> Option<usize> {
+        let upvalues = &mut self.states[state].upvalues;
+        if let Some(index) = upvalues.iter().position(|existing| *existing == upvalue) {
+            return Some(index);
+        }
+        if upvalues.len() > MAX_OPERAND {
+            self.error("E0402", "Too many closure variables in function.");
+            return Some(0);
+        }
+        upvalues.push(upvalue);
+        Some(upvalues.len() - 1)
+    }
+
+    /// Emits the instruction to read (`get`) or write `name`, as a local, captured or global
+    /// variable.
+    fn named_variable(&mut self, name: &Token, get: bool) {
+        self.at(name);
+        let current = self.states.len() - 1;
+        let (op, operand) = if let Some(slot) = self.resolve_local(current, name.symbol()) {
+            (if get { OpCode::GetLocal } else { OpCode::SetLocal }, slot)
+        } else if let Some(index) = self.resolve_upvalue(current, name.symbol()) {
+            (if get { OpCode::GetUpvalue } else { OpCode::SetUpvalue }, index)
+        } else {
+            let constant = self.identifier_constant(name.symbol());
+            (if get { OpCode::GetGlobal } else { OpCode::SetGlobal }, constant)
+        };
+        self.emit_with(op, operand);
+    }
+
+    /// Compiles `declaration` as a nested function, leaving a closure over it on the stack.
+    fn function(&mut self, declaration: &Function, kind: FunctionKind) {
+        self.states.push(FunctionState::new(declaration.name.symbol(), kind));
+        self.begin_scope();
+        for param in &declaration.params {
+            self.add_local(param.symbol().clone());
+        }
+        for stmt in &declaration.body {
+            self.visit_stmt(stmt);
+        }
+        self.emit_return();
+
+        let state = self.states.pop().expect("the function was pushed above");
+        let function = ObjFunction {
+            arity: declaration.params.len(),
+            upvalue_count: state.upvalues.len(),
+            ..state.function
+        };
+        self.at(&declaration.name);
+        let constant = self.make_constant(Constant::Function(Rc::new(function)));
+        let wide = std::iter::once(constant)
+            .chain(state.upvalues.iter().map(|upvalue| upvalue.index))
+            .any(|operand| operand > u8::MAX as usize);
+        if wide {
+            self.emit(OpCode::Wide);
+        }
+        self.emit(OpCode::Closure);
+        self.emit_operand(constant, wide);
+        for upvalue in state.upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_operand(upvalue.index, wide);
+        }
+    }
+
+    fn class(&mut self, name: &Token, superclass: Option<&Expression>, methods: &[Rc<Function>]) {
+        self.at(name);
+        let constant = self.identifier_constant(name.symbol());
+        self.emit_with(OpCode::Class, constant);
+        self.define_variable(name);
+
+        // Methods of a subclass capture `super` from a scope around the class body.
+        if let Some(superclass) = superclass {
+            self.visit(superclass);
+            self.begin_scope();
+            self.add_local(Symbol::super_());
+            self.named_variable(name, true);
+            self.at(Compiler::name_of(superclass));
+            self.emit(OpCode::Inherit);
+        }
+
+        self.named_variable(name, true);
+        for method in methods {
+            let kind = match method.name.symbol().as_str() {
+                "init" => FunctionKind::Initializer,
+                _ => FunctionKind::Method,
+            };
+            self.function(method, kind);
+            let constant = self.identifier_constant(method.name.symbol());
+            self.emit_with(OpCode::Method, constant);
+        }
+        self.emit(OpCode::Pop);
+
+        if superclass.is_some() {
+            self.end_scope();
+        }
+    }
+
+    /// The token to blame for a runtime error about a (superclass) variable expression.
+    fn name_of(expr: &Expression) -> &Token {
+        match expr {
+            Expression::Variable(name, _) => name,
+            _ => unreachable!("the parser only produces variables as superclasses"),
+        }
+    }
+
+    /// Reports the first error only, as the others are most likely caused by the same overflow.
+    fn error(&mut self, code: &'static str, message: &str) {
+        if self.had_error {
+            return;
+        }
+        self.had_error = true;
+        let diagnostic = Diagnostic::error(Phase::Compile, code, self.line, self.span, message);
+        self.reporter.borrow_mut().report(diagnostic);
+    }
+}
+
+impl Visitor for Compiler {
+    type Output = ();
+
+    fn visit(&mut self, expr: &Expression) -> Self::Output {
+        match expr {
+            Expression::Assign(name, value, _) => {
+                self.visit(value);
+                self.named_variable(name, false);
+            },
+            Expression::Binary(left, operator, right) => {
+                self.visit(left);
+                self.visit(right);
+                self.at_span(operator, left.span().to(right.span()));
+                self.emit(match operator.token_type {
+                    TokenType::BangEqual => OpCode::NotEqual,
+                    TokenType::EqualEqual => OpCode::Equal,
+                    TokenType::Greater => OpCode::Greater,
+                    TokenType::GreaterEqual => OpCode::GreaterEqual,
+                    TokenType::Less => OpCode::Less,
+                    TokenType::LessEqual => OpCode::LessEqual,
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Star => OpCode::Multiply,
+                    TokenType::Slash => OpCode::Divide,
+                    _ => unreachable!("the parser only produces binary arithmetic and comparisons"),
+                });
+            },
+            Expression::Call(callee, paren, arguments) => {
+                self.visit(callee);
+                for argument in arguments {
+                    self.visit(argument);
+                }
+                self.at(paren);
+                // The parser allows at most 255 arguments.
+                self.emit_with(OpCode::Call, arguments.len());
+            },
+            Expression::Get(object, name) => {
+                self.visit(object);
+                self.at(name);
+                let constant = self.identifier_constant(name.symbol());
+                self.emit_with(OpCode::GetProperty, constant);
+            },
+            Expression::Grouping(expr, _) => self.visit(expr),
+            Expression::Literal(literal, span) => {
+                if !self.line_starts.is_empty() {
+                    self.line = self.line_starts.partition_point(|&start| start <= span.offset);
+                }
+                self.span = *span;
+                match literal.as_ref().unwrap_or(&Literal::Null) {
+                    Literal::Null => self.emit(OpCode::Nil),
+                    Literal::Boolean(true) => self.emit(OpCode::True),
+                    Literal::Boolean(false) => self.emit(OpCode::False),
+                    Literal::Number(n) => {
+                        let constant = self.make_constant(Constant::Number(*n));
+                        self.emit_with(OpCode::Constant, constant);
+                    },
+                    Literal::String(s) => {
+                        let constant = self.make_constant(Constant::String(s.clone()));
+                        self.emit_with(OpCode::Constant, constant);
+                    },
+                }
+            },
+            Expression::Logical(left, operator, right) => {
+                // Short-circuit, leaving the deciding operand itself on the stack.
+                self.visit(left);
+                self.at(operator);
+                let end = if operator.token_type == TokenType::Or {
+                    let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    let end = self.emit_jump(OpCode::Jump);
+                    self.patch_jump(else_jump);
+                    end
+                } else {
+                    self.emit_jump(OpCode::JumpIfFalse)
+                };
+                self.emit(OpCode::Pop);
+                self.visit(right);
+                self.patch_jump(end);
+            },
+            Expression::Set(object, name, value) => {
+                self.visit(object);
+                self.visit(value);
+                self.at(name);
+                let constant = self.identifier_constant(name.symbol());
+                self.emit_with(OpCode::SetProperty, constant);
+            },
+            Expression::Super(keyword, method, _) => {
+                let this = Token { lexeme: Lexeme::Name(Symbol::this()), ..keyword.clone() };
+                self.named_variable(&this, true);
+                self.named_variable(keyword, true);
+                self.at(method);
+                let constant = self.identifier_constant(method.symbol());
+                self.emit_with(OpCode::GetSuper, constant);
+            },
+            Expression::This(keyword, _) => self.named_variable(keyword, true),
+            Expression::Unary(operator, right) => {
+                self.visit(right);
+                self.at_span(operator, operator.span.to(right.span()));
+                match operator.token_type {
+                    TokenType::Bang => self.emit(OpCode::Not),
+                    _ => self.emit(OpCode::Negate),
+                }
+            },
+            Expression::Variable(name, _) => self.named_variable(name, true),
+        }
+    }
+}
+
+impl StmtVisitor for Compiler {
+    type Output = ();
+
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Self::Output {
+        match stmt {
+            Stmt::Block(statements) => {
+                self.begin_scope();
+                for stmt in statements {
+                    self.visit_stmt(stmt);
+                }
+                self.end_scope();
+            },
+            Stmt::Class(name, superclass, methods) => {
+                self.class(name, superclass.as_ref(), methods)
+            },
+            Stmt::Expression(expr) => {
+                self.visit(expr);
+                self.emit(OpCode::Pop);
+            },
+            Stmt::Function(declaration) => {
+                // Declared before compiling the body so the function can refer to itself.
+                let is_local = self.state().scope_depth > 0;
+                if is_local {
+                    self.add_local(declaration.name.symbol().clone());
+                }
+                self.function(declaration, FunctionKind::Function);
+                if !is_local {
+                    self.define_variable(&declaration.name);
+                }
+            },
+            Stmt::If(condition, then_branch, else_branch) => {
+                self.visit(condition);
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+                self.visit_stmt(then_branch);
+                let else_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(then_jump);
+                self.emit(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.visit_stmt(else_branch);
+                }
+                self.patch_jump(else_jump);
+            },
+            Stmt::Print(expr) => {
+                self.visit(expr);
+                self.emit(OpCode::Print);
+            },
+            Stmt::Return(keyword, value) => match value {
+                Some(value) => {
+                    self.visit(value);
+                    self.at(keyword);
+                    self.emit(OpCode::Return);
+                },
+                None => {
+                    self.at(keyword);
+                    self.emit_return();
+                },
+            },
+            Stmt::Var(name, initializer) => {
+                match initializer {
+                    Some(expr) => self.visit(expr),
+                    None => self.emit(OpCode::Nil),
+                }
+                self.at(name);
+                self.define_variable(name);
+            },
+            Stmt::While(condition, body) => {
+                let start = self.chunk().code.len();
+                self.visit(condition);
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+                self.visit_stmt(body);
+                self.emit_loop(start);
+                self.patch_jump(exit_jump);
+                self.emit(OpCode::Pop);
+            },
+        }
+    }
+}
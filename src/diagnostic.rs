@@ -0,0 +1,266 @@
+use crate::interpreter::RuntimeError;
+use crate::lexer::{Span, Token};
+use std::fmt::{Display, Formatter};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+}
+
+/// The stage of the pipeline that found a problem.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Phase {
+    Scan,
+    Parse,
+    Resolve,
+    /// Compilation to bytecode, for the VM backend.
+    Compile,
+    Runtime,
+}
+
+/// A problem found in a program, along with where it was found.
+///
+/// `code` identifies the kind of problem independently of the wording of `message`, e.g. `E0101`
+/// for an invalid assignment target. Codes are grouped by phase: `E00xx` for the scanner,
+/// `E01xx` for the parser, `E02xx` for the resolver, `E03xx` at runtime and `E04xx` for the
+/// bytecode compiler.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub phase: Phase,
+    pub code: &'static str,
+    pub line: usize,
+    pub span: Span,
+    /// The lexeme of the token the problem was found at, empty at the end of the input.
+    pub lexeme: Option<String>,
+    pub message: String,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(phase: Phase, code: &'static str, line: usize, span: Span, message: &str) -> Self {
+        Self {
+            severity: Severity::Error,
+            phase,
+            code,
+            line,
+            span,
+            lexeme: None,
+            message: message.to_string(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// An error found at `token`.
+    pub fn at(token: &Token, phase: Phase, code: &'static str, message: &str) -> Self {
+        Self {
+            lexeme: Some(token.lexeme.to_string()),
+            ..Self::error(phase, code, token.line, token.span, message)
+        }
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// Renders the diagnostic on a single line, e.g. `[line 1] Error at 'x': message`, or as
+    /// `message\n[line N]` for runtime errors. Notes are left out.
+    pub fn render_plain(&self) -> String {
+        if self.phase == Phase::Runtime {
+            return format!("{}\n[line {}]", self.message, self.line);
+        }
+        let location = match self.lexeme.as_deref() {
+            None => String::new(),
+            Some("") => " at the end".to_string(),
+            Some(lexeme) => format!(" at '{lexeme}'"),
+        };
+        format!("[line {}] {}{location}: {}", self.line, self.severity, self.message)
+    }
+
+    /// Renders the diagnostic with the source line containing its span and a caret underline,
+    /// e.g.
+    ///
+    /// ```text
+    /// error: Expect ';' after value.
+    ///  --> line 1, column 8
+    ///   |
+    /// 1 | print 1
+    ///   |        ^
+    /// ```
+    pub fn render_rich(&self, source: Option<&str>) -> String {
+        let span = self.span;
+        let mut rendered =
+            format!("{}: {}", self.severity.to_string().to_lowercase(), self.message);
+        let Some(source) = source.filter(|source| source.is_char_boundary(span.offset)) else {
+            rendered.push_str(&format!("\n --> line {}", self.line));
+            return rendered;
+        };
+
+        let line_start = source[..span.offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[span.offset..].find('\n').map_or(source.len(), |i| span.offset + i);
+        // Tokens such as strings may span several lines; only the first one is shown.
+        let line = source[..span.offset].matches('\n').count() + 1;
+        let text = &source[line_start..line_end];
+        // Spans are in bytes, but the caret is placed in characters. Tabs are copied so that
+        // the caret lines up whatever their width.
+        let indent: String = source[line_start..span.offset]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let underlined =
+            source.get(span.offset..span.offset + span.len.min(line_end - span.offset));
+        let underline = underlined.map_or(1, |underlined| underlined.chars().count().max(1));
+        let gutter = " ".repeat(line.to_string().len());
+
+        rendered.push_str(&format!("\n{gutter}--> line {line}, column {}", span.column));
+        rendered.push_str(&format!("\n{gutter} |"));
+        rendered.push_str(&format!("\n{line} | {text}"));
+        rendered.push_str(&format!("\n{gutter} | {indent}{}", "^".repeat(underline)));
+        for note in &self.notes {
+            rendered.push_str(&format!("\n{gutter} = note: {note}"));
+        }
+        rendered
+    }
+
+    /// Renders the diagnostic as a single-line JSON object.
+    pub fn render_json(&self) -> String {
+        let notes: Vec<String> = self.notes.iter().map(|note| json_string(note)).collect();
+        format!(
+            concat!(
+                "{{\"severity\":\"{}\",\"phase\":\"{}\",\"code\":\"{}\",\"line\":{},",
+                "\"column\":{},\"offset\":{},\"length\":{},\"message\":{},\"notes\":[{}]}}"
+            ),
+            self.severity.to_string().to_lowercase(),
+            self.phase,
+            self.code,
+            self.line,
+            self.span.column,
+            self.span.offset,
+            self.span.len,
+            json_string(&self.message),
+            notes.join(",")
+        )
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(error: &RuntimeError) -> Self {
+        Diagnostic::error(Phase::Runtime, "E0300", error.token.line, error.span, &error.message)
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "Error"),
+        }
+    }
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let as_str = match self {
+            Phase::Scan => "scan",
+            Phase::Parse => "parse",
+            Phase::Resolve => "resolve",
+            Phase::Compile => "compile",
+            Phase::Runtime => "runtime",
+        };
+        write!(f, "{}", as_str)
+    }
+}
+
+/// Quotes `s` as a JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diagnostic::{Diagnostic, Phase};
+    use crate::lexer::{Span, Token, TokenType};
+
+    fn diagnostic() -> Diagnostic {
+        let token = Token::new(TokenType::Identifier, "b", None, 2).with_span(Span::new(22, 1, 12));
+        Diagnostic::at(&token, Phase::Resolve, "E0201", "Oops.").with_note("declare 'b' first")
+    }
+
+    #[test]
+    fn renders_plain() {
+        assert_eq!(diagnostic().render_plain(), "[line 2] Error at 'b': Oops.");
+        let eof = Token::new(TokenType::Eof, "", None, 4);
+        let diagnostic = Diagnostic::at(&eof, Phase::Parse, "E0100", "Oops.");
+        assert_eq!(diagnostic.render_plain(), "[line 4] Error at the end: Oops.");
+    }
+
+    #[test]
+    fn renders_rich() {
+        assert_eq!(
+            diagnostic().render_rich(Some("var a = 1;\nprint a +  b;\n")),
+            [
+                "error: Oops.",
+                " --> line 2, column 12",
+                "  |",
+                "2 | print a +  b;",
+                "  |            ^",
+                "  = note: declare 'b' first"
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn underlines_up_to_end_of_line() {
+        let diagnostic =
+            Diagnostic::error(Phase::Scan, "E0002", 2, Span::new(0, 9, 1), "Unterminated string.");
+        assert_eq!(
+            diagnostic.render_rich(Some("\"abc\ndef\"")),
+            "error: Unterminated string.\n --> line 1, column 1\n  |\n1 | \"abc\n  | ^^^^"
+        );
+    }
+
+    #[test]
+    fn places_caret_after_non_ascii_characters_and_tabs() {
+        let source = "\tprint \"h\u{e9}llo\" + x\u{e9};";
+        let span = Span::new(18, 3, 18);
+        let diagnostic = Diagnostic::error(Phase::Runtime, "E0300", 1, span, "Oops.");
+        assert_eq!(
+            diagnostic.render_rich(Some(source)),
+            [
+                "error: Oops.",
+                " --> line 1, column 18",
+                "  |",
+                "1 | \tprint \"h\u{e9}llo\" + x\u{e9};",
+                "  | \t                ^^"
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn renders_json() {
+        let diagnostic = diagnostic().with_note("tab\there, \"quoted\"");
+        assert_eq!(
+            diagnostic.render_json(),
+            concat!(
+                r#"{"severity":"error","phase":"resolve","code":"E0201","line":2,"column":12,"#,
+                r#""offset":22,"length":1,"message":"Oops.","#,
+                r#""notes":["declare 'b' first","tab\there, \"quoted\""]}"#
+            )
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use crate::chunk::{Chunk, Constant, OpCode};
+use crate::object::ObjFunction;
+
+/// Lists the instructions of `function`, followed by those of the functions declared in it, in
+/// the format of clox, e.g.
+///
+/// ```text
+/// == <script> ==
+/// 0000    1 OP_CONSTANT         0 '1.2'
+/// 0002    | OP_PRINT
+/// ```
+pub fn disassemble(function: &ObjFunction) -> String {
+    let mut listing = format!("== {} ==\n", function);
+    let chunk = &function.chunk;
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let (instruction, next) = disassemble_instruction(chunk, offset);
+        listing.push_str(&instruction);
+        listing.push('\n');
+        offset = next;
+    }
+    for constant in &chunk.constants {
+        if let Constant::Function(function) = constant {
+            listing.push('\n');
+            listing.push_str(&disassemble(function));
+        }
+    }
+    listing
+}
+
+/// Renders the instruction at `offset`, which may span several lines for closures and widened
+/// instructions, and returns the offset of the next instruction.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> (String, usize) {
+    instruction(chunk, offset, false)
+}
+
+/// Renders the instruction at `offset`, whose operands are three bytes long if it is `wide`.
+fn instruction(chunk: &Chunk, offset: usize, wide: bool) -> (String, usize) {
+    let line = chunk.lines[offset];
+    let mut text = if offset > 0 && chunk.lines[offset - 1] == line {
+        format!("{:04}    | ", offset)
+    } else {
+        format!("{:04} {:>4} ", offset, line)
+    };
+
+    let op = match OpCode::try_from(chunk.code[offset]) {
+        Ok(op) => op,
+        Err(byte) => {
+            text.push_str(&format!("Unknown opcode {}", byte));
+            return (text, offset + 1);
+        },
+    };
+    let operand = |index: usize| chunk.code[offset + index];
+    // The size of an index operand, and the operand at `index` bytes into the instruction.
+    let width = if wide { 3 } else { 1 };
+    let index_operand = |index: usize| {
+        if wide {
+            u32::from_be_bytes([0, operand(index), operand(index + 1), operand(index + 2)]) as usize
+        } else {
+            operand(index) as usize
+        }
+    };
+    let next = match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Class
+        | OpCode::Method => {
+            let constant = index_operand(1);
+            let value = &chunk.constants[constant];
+            text.push_str(&format!("{:<16} {:>4} '{}'", op.to_string(), constant, value));
+            offset + 1 + width
+        },
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call => {
+            text.push_str(&format!("{:<16} {:>4}", op.to_string(), index_operand(1)));
+            offset + 1 + width
+        },
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+            let jump = u16::from_be_bytes([operand(1), operand(2)]) as usize;
+            let target = if op == OpCode::Loop { offset + 3 - jump } else { offset + 3 + jump };
+            text.push_str(&format!("{:<16} {:>4} -> {}", op.to_string(), offset, target));
+            offset + 3
+        },
+        OpCode::Closure => {
+            let constant = index_operand(1);
+            let value = &chunk.constants[constant];
+            text.push_str(&format!("{:<16} {:>4} {}", op.to_string(), constant, value));
+            let upvalue_count = match value {
+                Constant::Function(function) => function.upvalue_count,
+                _ => 0,
+            };
+            let mut next = offset + 1 + width;
+            for _ in 0..upvalue_count {
+                let kind = if chunk.code[next] == 1 { "local" } else { "upvalue" };
+                let index = index_operand(next + 1 - offset);
+                text.push_str(&format!(
+                    "\n{:04}      |                     {} {}",
+                    next, kind, index
+                ));
+                next += 1 + width;
+            }
+            next
+        },
+        OpCode::Wide => {
+            text.push_str(&op.to_string());
+            let (widened, next) = instruction(chunk, offset + 1, true);
+            text.push('\n');
+            text.push_str(&widened);
+            next
+        },
+        _ => {
+            text.push_str(&op.to_string());
+            offset + 1
+        },
+    };
+    (text, next)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::disassembler::disassemble;
+    use crate::test_support;
+
+    fn compile(source: &str) -> String {
+        disassemble(&test_support::compile(source))
+    }
+
+    #[test]
+    fn lists_instructions_with_lines_and_operands() {
+        assert_eq!(
+            compile("print 1.2;\nvar a = \"x\";\nwhile (a) a = nil;"),
+            [
+                "== <script> ==",
+                "0000    1 OP_CONSTANT         0 '1.2'",
+                "0002    | OP_PRINT",
+                "0003    2 OP_CONSTANT         1 'x'",
+                "0005    | OP_DEFINE_GLOBAL    2 'a'",
+                "0007    3 OP_GET_GLOBAL       2 'a'",
+                "0009    | OP_JUMP_IF_FALSE    9 -> 20",
+                "0012    | OP_POP",
+                "0013    | OP_NIL",
+                "0014    | OP_SET_GLOBAL       2 'a'",
+                "0016    | OP_POP",
+                "0017    | OP_LOOP            17 -> 7",
+                "0020    | OP_POP",
+                "0021    | OP_NIL",
+                "0022    | OP_RETURN",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn lists_nested_functions_and_captures() {
+        let listing = compile("fun outer() {\n  var x = 1;\n  fun inner() { return x; }\n}");
+        assert!(listing.contains(
+            "0002    3 OP_CLOSURE          1 <fn inner>\n0004      |                     local 1\n"
+        ));
+        assert!(listing.contains("\n== <fn inner> ==\n0000    3 OP_GET_UPVALUE      0\n"));
+    }
+
+    #[test]
+    fn lists_widened_instructions() {
+        let source: String = (0..257).map(|n| format!("print {n};\n")).collect();
+        assert!(compile(&source).contains(
+            "0768  257 OP_WIDE\n0769    | OP_CONSTANT       256 '256'\n0773    | OP_PRINT\n"
+        ));
+    }
+}
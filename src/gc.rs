@@ -0,0 +1,418 @@
+use crate::chunk::Constant;
+use crate::lexer::Span;
+use crate::object::{ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, Upvalue};
+use crate::symbol::Symbol;
+use crate::value::Value;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+use std::rc::{Rc, Weak};
+
+/// Settings of the VM's garbage collector, set with the `--gc-threshold`, `--gc-growth` and
+/// `--gc-stress` options of the command line.
+///
+/// Only the VM collects garbage. The tree-walking interpreter relies on reference counting
+/// alone, so reference cycles between its environments, functions and instances are leaked, and
+/// the command line rejects the options above unless the VM runs the program.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GcConfig {
+    /// The number of bytes allocated before the first collection.
+    pub initial_threshold: usize,
+    /// After a collection, the next one runs once the heap has grown to this many times the
+    /// size of the objects that survived.
+    pub growth_factor: usize,
+    /// Collect before every allocation, to flush out objects that are used without being
+    /// reachable from the roots.
+    pub stress: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self { initial_threshold: 1024 * 1024, growth_factor: 2, stress: false }
+    }
+}
+
+/// An object allocated by the VM. Only weak references are kept, so that the heap never keeps
+/// an object alive by itself.
+#[derive(Clone)]
+pub enum Tracked {
+    String(Weak<str>),
+    Function(Weak<ObjFunction>),
+    Closure(Weak<ObjClosure>),
+    Upvalue(Weak<RefCell<Upvalue>>),
+    Class(Weak<ObjClass>),
+    Instance(Weak<RefCell<ObjInstance>>),
+    BoundMethod(Weak<ObjBoundMethod>),
+}
+
+impl Tracked {
+    /// The object `value` refers to, if it was allocated by the VM.
+    fn of(value: &Value) -> Option<Tracked> {
+        let object = match value {
+            Value::String(s) => Tracked::String(Rc::downgrade(s.as_rc())),
+            Value::Closure(closure) => Tracked::Closure(Rc::downgrade(closure)),
+            Value::BoundMethod(method) => Tracked::BoundMethod(Rc::downgrade(method)),
+            Value::ObjClass(class) => Tracked::Class(Rc::downgrade(class)),
+            Value::ObjInstance(instance) => Tracked::Instance(Rc::downgrade(instance)),
+            // Tree-walking interpreter objects and host objects are never allocated by the VM.
+            Value::Nil
+            | Value::Boolean(_)
+            | Value::Number(_)
+            | Value::Callable(_)
+            | Value::Class(_)
+            | Value::Instance(_)
+            | Value::Native(_) => return None,
+        };
+        Some(object)
+    }
+
+    /// The address of the object, identifying it while it is alive.
+    fn address(&self) -> usize {
+        match self {
+            Tracked::String(s) => s.as_ptr() as *const u8 as usize,
+            Tracked::Function(function) => function.as_ptr() as usize,
+            Tracked::Closure(closure) => closure.as_ptr() as usize,
+            Tracked::Upvalue(upvalue) => upvalue.as_ptr() as usize,
+            Tracked::Class(class) => class.as_ptr() as usize,
+            Tracked::Instance(instance) => instance.as_ptr() as usize,
+            Tracked::BoundMethod(method) => method.as_ptr() as usize,
+        }
+    }
+
+    /// The number of strong references to the object, wherever they are held.
+    fn strong_count(&self) -> usize {
+        match self {
+            Tracked::String(s) => s.strong_count(),
+            Tracked::Function(function) => function.strong_count(),
+            Tracked::Closure(closure) => closure.strong_count(),
+            Tracked::Upvalue(upvalue) => upvalue.strong_count(),
+            Tracked::Class(class) => class.strong_count(),
+            Tracked::Instance(instance) => instance.strong_count(),
+            Tracked::BoundMethod(method) => method.strong_count(),
+        }
+    }
+
+    /// The objects the object holds a strong reference to, once per reference.
+    fn references(&self) -> Vec<Tracked> {
+        let mut references = Vec::new();
+        match self {
+            Tracked::String(_) => {},
+            Tracked::Function(function) => {
+                let Some(function) = function.upgrade() else { return references };
+                for constant in &function.chunk.constants {
+                    if let Constant::Function(function) = constant {
+                        references.push(Tracked::Function(Rc::downgrade(function)));
+                    }
+                }
+            },
+            Tracked::Closure(closure) => {
+                let Some(closure) = closure.upgrade() else { return references };
+                references.push(Tracked::Function(Rc::downgrade(&closure.function)));
+                for upvalue in &closure.upvalues {
+                    references.push(Tracked::Upvalue(Rc::downgrade(upvalue)));
+                }
+            },
+            Tracked::Upvalue(upvalue) => {
+                let Some(upvalue) = upvalue.upgrade() else { return references };
+                // Open upvalues point into the stack, which is a root.
+                let upvalue = upvalue.borrow();
+                if let Upvalue::Closed(value) = &*upvalue {
+                    references.extend(Tracked::of(value));
+                }
+            },
+            Tracked::Class(class) => {
+                let Some(class) = class.upgrade() else { return references };
+                for method in class.methods.borrow().values() {
+                    references.push(Tracked::Closure(Rc::downgrade(method)));
+                }
+            },
+            Tracked::Instance(instance) => {
+                let Some(instance) = instance.upgrade() else { return references };
+                let instance = instance.borrow();
+                references.push(Tracked::Class(Rc::downgrade(&instance.class)));
+                references.extend(instance.fields.values().filter_map(Tracked::of));
+            },
+            Tracked::BoundMethod(method) => {
+                let Some(method) = method.upgrade() else { return references };
+                references.extend(Tracked::of(&method.receiver));
+                references.push(Tracked::Closure(Rc::downgrade(&method.method)));
+            },
+        }
+        references
+    }
+
+    /// An estimate of the memory used by the object, or `None` once it has been freed.
+    fn size(&self) -> Option<usize> {
+        let size = match self {
+            Tracked::String(s) => size_of::<Rc<str>>() + s.upgrade()?.len(),
+            Tracked::Function(function) => {
+                let chunk = &function.upgrade()?.chunk;
+                size_of::<ObjFunction>()
+                    + chunk.code.len() * (1 + size_of::<usize>() + size_of::<Span>())
+                    + chunk.constants.len() * size_of::<Constant>()
+            },
+            Tracked::Closure(closure) => {
+                size_of::<ObjClosure>() + closure.upgrade()?.upvalues.len() * size_of::<usize>()
+            },
+            Tracked::Upvalue(upvalue) => {
+                upvalue.upgrade()?;
+                size_of::<RefCell<Upvalue>>()
+            },
+            Tracked::Class(class) => {
+                let methods = class.upgrade()?.methods.borrow().len();
+                size_of::<ObjClass>() + methods * size_of::<(Symbol, Rc<ObjClosure>)>()
+            },
+            Tracked::Instance(instance) => {
+                let fields = instance.upgrade()?.borrow().fields.len();
+                size_of::<RefCell<ObjInstance>>() + fields * size_of::<(Symbol, Value)>()
+            },
+            Tracked::BoundMethod(method) => {
+                method.upgrade()?;
+                size_of::<ObjBoundMethod>()
+            },
+        };
+        Some(size)
+    }
+
+    /// Drops the references an unreachable object holds to other objects. Reference cycles
+    /// always go through a field, a method table or a closed upvalue, so clearing those frees
+    /// every object of the cycle.
+    fn clear(&self) {
+        match self {
+            Tracked::Upvalue(upvalue) => {
+                if let Some(upvalue) = upvalue.upgrade() {
+                    *upvalue.borrow_mut() = Upvalue::Closed(Value::Nil);
+                }
+            },
+            Tracked::Class(class) => {
+                if let Some(class) = class.upgrade() {
+                    class.methods.borrow_mut().clear();
+                }
+            },
+            Tracked::Instance(instance) => {
+                if let Some(instance) = instance.upgrade() {
+                    instance.borrow_mut().fields.clear();
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+/// The objects allocated by the VM, and a mark-and-sweep collector for them.
+///
+/// Objects are reference counted, which frees most of them as soon as they are no longer used.
+/// The collector takes care of the rest: objects only reachable from each other, such as an
+/// instance holding a closure that captured the instance. A collection starts by marking the
+/// roots, i.e. the stack and global variables, with the `mark_*` methods, then [`Heap::collect`]
+/// marks everything they reference and breaks up the objects left unmarked.
+///
+/// Objects held by the host application, such as the value returned by
+/// [`Lox::eval_str`](crate::lox::Lox::eval_str), are found by counting references: an unmarked
+/// object with more strong references than the other unmarked objects hold to it is referenced
+/// from outside the heap, so it is kept along with everything it references.
+pub struct Heap {
+    config: GcConfig,
+    objects: Vec<Tracked>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    /// The addresses of the objects marked so far by the collection in progress.
+    marked: HashSet<usize>,
+    /// Marked objects whose references are yet to be marked.
+    gray: Vec<Tracked>,
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Self {
+        Self {
+            config,
+            objects: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: config.initial_threshold,
+            marked: HashSet::new(),
+            gray: Vec::new(),
+        }
+    }
+
+    /// Whether enough memory has been allocated since the last collection to run another one.
+    pub fn should_collect(&self) -> bool {
+        self.config.stress || self.bytes_allocated > self.next_gc
+    }
+
+    /// Starts tracking a newly allocated object.
+    pub fn track(&mut self, object: Tracked) {
+        self.bytes_allocated += object.size().unwrap_or(0);
+        self.objects.push(object);
+    }
+
+    /// Tracks `function` along with the functions declared in it.
+    pub fn track_function(&mut self, function: &Rc<ObjFunction>) {
+        self.track(Tracked::Function(Rc::downgrade(function)));
+        for constant in &function.chunk.constants {
+            if let Constant::Function(function) = constant {
+                self.track_function(function);
+            }
+        }
+    }
+
+    pub fn mark_value(&mut self, value: &Value) {
+        if let Some(object) = Tracked::of(value) {
+            self.mark(object);
+        }
+    }
+
+    pub fn mark_closure(&mut self, closure: &Rc<ObjClosure>) {
+        self.mark(Tracked::Closure(Rc::downgrade(closure)));
+    }
+
+    pub fn mark_upvalue(&mut self, upvalue: &Rc<RefCell<Upvalue>>) {
+        self.mark(Tracked::Upvalue(Rc::downgrade(upvalue)));
+    }
+
+    fn mark(&mut self, object: Tracked) {
+        if self.marked.insert(object.address()) {
+            self.gray.push(object);
+        }
+    }
+
+    /// Marks everything referenced by the objects marked so far.
+    fn trace(&mut self) {
+        while let Some(object) = self.gray.pop() {
+            for reference in object.references() {
+                self.mark(reference);
+            }
+        }
+    }
+
+    /// Marks everything reachable from the roots marked so far or held by the host application,
+    /// then frees every other object.
+    pub fn collect(&mut self) {
+        self.trace();
+
+        // Whatever holds the references unaccounted for by other unmarked objects is a root the
+        // VM doesn't know about.
+        let unmarked: Vec<Tracked> = self
+            .objects
+            .iter()
+            .filter(|object| object.size().is_some() && !self.marked.contains(&object.address()))
+            .cloned()
+            .collect();
+        let mut held_by_unmarked: HashMap<usize, usize> = HashMap::new();
+        for object in &unmarked {
+            for reference in object.references() {
+                *held_by_unmarked.entry(reference.address()).or_default() += 1;
+            }
+        }
+        for object in unmarked {
+            let held = held_by_unmarked.get(&object.address()).copied().unwrap_or(0);
+            if object.strong_count() > held {
+                self.mark(object);
+            }
+        }
+        self.trace();
+
+        let marked = std::mem::take(&mut self.marked);
+        let mut unreachable = Vec::new();
+        self.objects.retain(|object| {
+            if object.size().is_none() {
+                return false;
+            }
+            let reachable = marked.contains(&object.address());
+            if !reachable {
+                unreachable.push(object.clone());
+            }
+            reachable
+        });
+        // Clearing an object may free others, so only start once every object has been checked.
+        for object in unreachable {
+            object.clear();
+        }
+
+        self.bytes_allocated = self.objects.iter().filter_map(Tracked::size).sum();
+        self.next_gc =
+            (self.bytes_allocated * self.config.growth_factor).max(self.config.initial_threshold);
+    }
+
+    /// The number of objects tracked, freed or not.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gc::{GcConfig, Heap, Tracked};
+    use crate::object::{ObjClass, ObjClosure, ObjFunction, ObjInstance, Upvalue};
+    use crate::symbol::Symbol;
+    use crate::value::Value;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// An instance holding a closure that captured the instance.
+    fn cycle(heap: &mut Heap) -> Value {
+        let class = Rc::new(ObjClass::new("A"));
+        let instance = Rc::new(RefCell::new(ObjInstance::new(class.clone())));
+        let upvalue = Rc::new(RefCell::new(Upvalue::Closed(Value::ObjInstance(instance.clone()))));
+        let function = Rc::new(ObjFunction::default());
+        let closure = Rc::new(ObjClosure { function, upvalues: vec![upvalue.clone()] });
+        instance.borrow_mut().fields.insert(Symbol::intern("f"), Value::Closure(closure.clone()));
+
+        heap.track(Tracked::Class(Rc::downgrade(&class)));
+        heap.track(Tracked::Instance(Rc::downgrade(&instance)));
+        heap.track(Tracked::Upvalue(Rc::downgrade(&upvalue)));
+        heap.track(Tracked::Closure(Rc::downgrade(&closure)));
+        Value::ObjInstance(instance)
+    }
+
+    #[test]
+    fn frees_unreachable_cycles() {
+        let mut heap = Heap::new(GcConfig::default());
+        let garbage = match cycle(&mut heap) {
+            Value::ObjInstance(instance) => Rc::downgrade(&instance),
+            _ => unreachable!(),
+        };
+        let root = cycle(&mut heap);
+        assert_eq!(heap.len(), 8);
+        assert!(garbage.upgrade().is_some(), "reference counting alone leaks cycles");
+
+        heap.mark_value(&root);
+        heap.collect();
+        assert!(garbage.upgrade().is_none());
+        assert_eq!(heap.len(), 4);
+        let Value::ObjInstance(root) = root else { unreachable!() };
+        assert!(root.borrow().fields.contains_key(&Symbol::intern("f")));
+    }
+
+    #[test]
+    fn keeps_objects_held_outside_the_heap() {
+        let mut heap = Heap::new(GcConfig::default());
+        let held = cycle(&mut heap);
+        let Value::ObjInstance(instance) = &held else { unreachable!() };
+        let closure = match instance.borrow().fields.get(&Symbol::intern("f")) {
+            Some(Value::Closure(closure)) => Rc::downgrade(closure),
+            _ => unreachable!(),
+        };
+        drop(cycle(&mut heap));
+
+        heap.collect();
+        assert_eq!(heap.len(), 4);
+        assert!(instance.borrow().fields.contains_key(&Symbol::intern("f")));
+        let closure = closure.upgrade().unwrap();
+        assert!(matches!(*closure.upvalues[0].borrow(), Upvalue::Closed(Value::ObjInstance(_))));
+    }
+
+    #[test]
+    fn collects_once_the_heap_has_grown() {
+        let config = GcConfig { initial_threshold: 200, growth_factor: 2, stress: false };
+        let mut heap = Heap::new(config);
+        assert!(!heap.should_collect());
+        let _roots: Vec<Value> = (0..4).map(|_| cycle(&mut heap)).collect();
+        assert!(heap.should_collect());
+        heap.collect();
+        assert!(!heap.should_collect());
+
+        let heap = Heap::new(GcConfig { stress: true, ..config });
+        assert!(heap.should_collect());
+    }
+}
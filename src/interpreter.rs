@@ -1,141 +1,858 @@
-use crate::ast::{Expression, ObjectValue, Visitor};
-use crate::lexer::{Literal, Token, TokenType};
+use crate::ast::{Depth, Expression, Stmt, StmtVisitor, Visitor};
+use crate::callable::{LoxCallable, LoxFunction};
+use crate::class::{LoxClass, LoxInstance};
+use crate::lexer::{Span, Token, TokenType};
+use crate::lox::MAX_CALL_DEPTH;
+use crate::native::builtins;
+use crate::output::Output;
+use crate::symbol::{Symbol, SymbolMap};
+use crate::value::Value;
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::ops::ControlFlow;
+use std::rc::Rc;
 
-pub struct Interpreter {}
+/// Variable bindings of a single scope, chained to the scope enclosing it.
+pub struct Environment {
+    values: SymbolMap<Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new(enclosing: Option<Rc<RefCell<Environment>>>) -> Self {
+        Self { values: SymbolMap::default(), enclosing }
+    }
+
+    /// Binds `name` in this scope. Redefining an existing variable is allowed.
+    pub fn define(&mut self, name: Symbol, value: Value) {
+        self.values.insert(name, value);
+    }
+
+    /// Looks `name` up in this scope and then in the enclosing ones.
+    pub fn get(&self, name: &Token) -> Option<Value> {
+        match self.values.get(name.symbol()) {
+            Some(value) => Some(value.clone()),
+            None => self.enclosing.as_ref()?.borrow().get(name),
+        }
+    }
+
+    /// Rebinds an existing variable in the innermost scope that declares it.
+    pub fn assign(&mut self, name: &Token, value: Value) -> Option<()> {
+        if let Some(slot) = self.values.get_mut(name.symbol()) {
+            *slot = value;
+            return Some(());
+        }
+        self.enclosing.as_ref()?.borrow_mut().assign(name, value)
+    }
+
+    /// Looks `name` up exactly `distance` scopes out, as computed by the resolver.
+    pub fn get_at(&self, distance: usize, name: &Symbol) -> Option<Value> {
+        if distance == 0 {
+            return self.values.get(name).cloned();
+        }
+        self.enclosing.as_ref()?.borrow().get_at(distance - 1, name)
+    }
+
+    /// Rebinds `name` exactly `distance` scopes out, as computed by the resolver.
+    pub fn assign_at(&mut self, distance: usize, name: &Symbol, value: Value) -> Option<()> {
+        if distance == 0 {
+            return self.values.get_mut(name).map(|slot| *slot = value);
+        }
+        self.enclosing.as_ref()?.borrow_mut().assign_at(distance - 1, name, value)
+    }
+}
+
+/// The outcome of executing a statement: either carry on with the next one, or unwind to the
+/// enclosing function call with a returned value.
+pub type Completion = ControlFlow<Value>;
+
+pub type RuntimeResult<T> = std::result::Result<T, RuntimeError>;
+
+/// An error raised while executing a program, pointing at the token that caused it.
+#[derive(Debug)]
+pub struct RuntimeError {
+    /// Boxed to keep `RuntimeResult` small on the happy path.
+    pub token: Box<Token>,
+    pub message: String,
+    /// The offending source code, which may be wider than `token`, e.g. a whole binary expression.
+    pub span: Span,
+}
+
+impl RuntimeError {
+    pub fn new(token: &Token, message: &str) -> Self {
+        Self { token: Box::new(token.clone()), message: message.to_string(), span: token.span }
+    }
+
+    pub fn with_span(self, span: Span) -> Self {
+        Self { span, ..self }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+pub struct Interpreter {
+    globals: Rc<RefCell<Environment>>,
+    environment: Rc<RefCell<Environment>>,
+    /// Where `print` statements write to.
+    output: Output,
+    /// The number of function calls in progress.
+    call_depth: usize,
+}
 
 impl Interpreter {
     pub fn new() -> Self {
-        Self {}
+        let mut globals = Environment::new(None);
+        for native in builtins() {
+            let native = Rc::new(native);
+            globals.define(Symbol::intern(native.name()), Value::Callable(native.clone()));
+        }
+        let globals = Rc::new(RefCell::new(globals));
+        Self { environment: globals.clone(), globals, output: Output::stdout(), call_depth: 0 }
+    }
+
+    /// Sends the output of `print` statements to `output` from now on.
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.borrow().values.get(&Symbol::lookup(name)?).cloned()
+    }
+
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().define(Symbol::intern(name), value);
+    }
+
+    /// The names of all variables, functions and classes defined globally.
+    pub fn global_names(&self) -> Vec<String> {
+        self.globals.borrow().values.keys().map(|name| name.to_string()).collect()
     }
 }
 
 impl Visitor for Interpreter {
-    type Output = Option<ObjectValue>;
+    type Output = RuntimeResult<Value>;
 
-    fn visit(&self, expr: &Expression) -> Self::Output {
+    fn visit(&mut self, expr: &Expression) -> Self::Output {
         match expr {
-            Expression::Literal(l) => l.clone(),
-            Expression::Grouping(expr) => self.evaluate(expr),
+            Expression::Assign(name, value, depth) => {
+                let value = self.evaluate(value)?;
+                let assigned = match depth.get() {
+                    Some(distance) => self.environment.borrow_mut().assign_at(
+                        distance,
+                        name.symbol(),
+                        value.clone(),
+                    ),
+                    None => self.globals.borrow_mut().assign(name, value.clone()),
+                };
+                if assigned.is_none() {
+                    let message = format!("Undefined variable '{}'.", name.lexeme);
+                    return Err(RuntimeError::new(name, &message));
+                }
+                Ok(value)
+            },
+            Expression::Call(callee, paren, arguments) => {
+                self.evaluate_call(callee, paren, arguments)
+            },
+            Expression::Get(object, name) => {
+                let Value::Instance(instance) = self.evaluate(object)? else {
+                    return Err(RuntimeError::new(name, "Only instances have properties."));
+                };
+                LoxInstance::get(&instance, name).ok_or_else(|| {
+                    RuntimeError::new(name, &format!("Undefined property '{}'.", name.lexeme))
+                })
+            },
+            Expression::Literal(l, _) => Ok(l.as_ref().map_or(Value::Nil, Value::from)),
+            Expression::Logical(left, operator, right) => {
+                let left = self.evaluate(left)?;
+                // Short-circuit, yielding the deciding operand itself rather than a boolean.
+                let is_decided = match operator.token_type {
+                    TokenType::Or => left.is_truthy(),
+                    _ => !left.is_truthy(),
+                };
+                if is_decided {
+                    Ok(left)
+                } else {
+                    self.evaluate(right)
+                }
+            },
+            Expression::Grouping(expr, _) => self.evaluate(expr),
+            Expression::Set(object, name, value) => {
+                let Value::Instance(instance) = self.evaluate(object)? else {
+                    return Err(RuntimeError::new(name, "Only instances have fields."));
+                };
+                let value = self.evaluate(value)?;
+                instance.borrow_mut().set(name, value.clone());
+                Ok(value)
+            },
+            Expression::Super(keyword, method, depth) => {
+                self.evaluate_super(keyword, method, depth)
+            },
+            Expression::This(keyword, depth) => self.look_up_variable(keyword, depth),
             Expression::Unary(operator, expr) => self.evaluate_unary(operator, expr),
             Expression::Binary(left, operator, right) => {
                 self.evaluate_binary(left, operator, right)
             },
+            Expression::Variable(name, depth) => self.look_up_variable(name, depth),
+        }
+    }
+}
+
+impl StmtVisitor for Interpreter {
+    type Output = RuntimeResult<Completion>;
+
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Self::Output {
+        match stmt {
+            Stmt::Block(statements) => {
+                let environment = Environment::new(Some(self.environment.clone()));
+                return self.execute_block(statements, environment);
+            },
+            Stmt::Class(name, superclass, methods) => {
+                let superclass = match superclass {
+                    Some(superclass) => match self.evaluate(superclass)? {
+                        Value::Class(class) => Some(class),
+                        _ => {
+                            let message = "Superclass must be a class.";
+                            return Err(RuntimeError::new(
+                                Interpreter::name_of(superclass),
+                                message,
+                            ));
+                        },
+                    },
+                    None => None,
+                };
+                self.environment.borrow_mut().define(name.symbol().clone(), Value::Nil);
+
+                // Methods of a subclass close over an extra scope binding `super`.
+                let mut closure = self.environment.clone();
+                if let Some(superclass) = &superclass {
+                    let mut environment = Environment::new(Some(closure));
+                    environment.define(Symbol::super_(), Value::Class(superclass.clone()));
+                    closure = Rc::new(RefCell::new(environment));
+                }
+                let methods = methods
+                    .iter()
+                    .map(|method| {
+                        let is_initializer = *method.name.symbol() == Symbol::init();
+                        let function =
+                            LoxFunction::new(method.clone(), closure.clone(), is_initializer);
+                        (method.name.symbol().clone(), Rc::new(function))
+                    })
+                    .collect();
+                let class = LoxClass::new(name.symbol(), superclass, methods);
+                self.environment.borrow_mut().assign(name, Value::Class(Rc::new(class)));
+            },
+            Stmt::Expression(expr) => {
+                self.evaluate(expr)?;
+            },
+            Stmt::Function(declaration) => {
+                let function =
+                    LoxFunction::new(declaration.clone(), self.environment.clone(), false);
+                self.environment
+                    .borrow_mut()
+                    .define(declaration.name.symbol().clone(), Value::Callable(Rc::new(function)));
+            },
+            Stmt::If(condition, then_branch, else_branch) => {
+                let condition = self.evaluate(condition)?;
+                if condition.is_truthy() {
+                    return self.execute(then_branch);
+                } else if let Some(else_branch) = else_branch {
+                    return self.execute(else_branch);
+                }
+            },
+            Stmt::Print(expr) => {
+                let value = self.evaluate(expr)?;
+                self.output.write_line(&value.to_string());
+            },
+            Stmt::Return(_, value) => {
+                let value = match value {
+                    Some(expr) => self.evaluate(expr)?,
+                    None => Value::Nil,
+                };
+                return Ok(Completion::Break(value));
+            },
+            Stmt::Var(name, initializer) => {
+                let value = match initializer {
+                    Some(expr) => self.evaluate(expr)?,
+                    None => Value::Nil,
+                };
+                self.environment.borrow_mut().define(name.symbol().clone(), value);
+            },
+            Stmt::While(condition, body) => {
+                while self.evaluate(condition)?.is_truthy() {
+                    if let Completion::Break(value) = self.execute(body)? {
+                        return Ok(Completion::Break(value));
+                    }
+                }
+            },
         }
+        Ok(Completion::Continue(()))
     }
 }
 
 impl Interpreter {
-    pub fn interpret(&self, expr: &Expression) {
-        let value = self.evaluate(expr).map(|v| v.to_string()).unwrap_or("".to_string());
-        println!("{}", value);
+    /// Executes `statements` in order, stopping at the first one that fails.
+    pub fn interpret(&mut self, statements: &[Stmt]) -> RuntimeResult<()> {
+        for stmt in statements {
+            // The resolver rejects top-level `return`, but stop gracefully if one slips through.
+            if self.execute(stmt)?.is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn execute(&mut self, stmt: &Stmt) -> RuntimeResult<Completion> {
+        self.visit_stmt(stmt)
+    }
+
+    /// Executes `statements` in `environment`, restoring the current environment afterwards even
+    /// if one of the statements fails or returns.
+    pub fn execute_block(
+        &mut self,
+        statements: &[Stmt],
+        environment: Environment,
+    ) -> RuntimeResult<Completion> {
+        let previous = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+        let mut result = Ok(Completion::Continue(()));
+        for stmt in statements {
+            result = self.execute(stmt);
+            if !matches!(result, Ok(Completion::Continue(()))) {
+                break;
+            }
+        }
+        self.environment = previous;
+        result
     }
-    pub fn evaluate(&self, expr: &Expression) -> Option<ObjectValue> {
+
+    /// Executes the body of a function called at `paren`, failing instead of overflowing the
+    /// native stack when calls nest too deep.
+    pub fn execute_call(
+        &mut self,
+        body: &[Stmt],
+        environment: Environment,
+        paren: &Token,
+    ) -> RuntimeResult<Completion> {
+        if self.call_depth == MAX_CALL_DEPTH {
+            return Err(RuntimeError::new(paren, "Stack overflow."));
+        }
+        self.call_depth += 1;
+        let result = self.execute_block(body, environment);
+        self.call_depth -= 1;
+        result
+    }
+
+    pub fn evaluate(&mut self, expr: &Expression) -> RuntimeResult<Value> {
         self.visit(expr)
     }
 
-    fn evaluate_unary(&self, operator: &Token, expr: &Expression) -> Option<ObjectValue> {
+    fn look_up_variable(&self, name: &Token, depth: &Depth) -> RuntimeResult<Value> {
+        let value = match depth.get() {
+            Some(distance) => self.environment.borrow().get_at(distance, name.symbol()),
+            None => self.globals.borrow().get(name),
+        };
+        value.ok_or_else(|| {
+            RuntimeError::new(name, &format!("Undefined variable '{}'.", name.lexeme))
+        })
+    }
+
+    /// The token to blame for a runtime error about a (superclass) variable expression.
+    fn name_of(expr: &Expression) -> &Token {
+        match expr {
+            Expression::Variable(name, _) => name,
+            _ => unreachable!("the parser only produces variables as superclasses"),
+        }
+    }
+
+    /// Looks `method` up starting at the superclass, binding it to the current `this`.
+    fn evaluate_super(
+        &self,
+        keyword: &Token,
+        method: &Token,
+        depth: &Depth,
+    ) -> RuntimeResult<Value> {
+        let environment = self.environment.borrow();
+        let superclass = depth.get().and_then(|distance| {
+            // `this` is always bound in the scope just inside the one binding `super`.
+            let superclass = environment.get_at(distance, keyword.symbol())?;
+            let instance = environment.get_at(distance.checked_sub(1)?, &Symbol::this())?;
+            Some((superclass, instance))
+        });
+        let Some((Value::Class(superclass), Value::Instance(instance))) = superclass else {
+            return Err(RuntimeError::new(keyword, "Undefined variable 'super'."));
+        };
+        let Some(method) = superclass.find_method(method.symbol()) else {
+            let message = format!("Undefined property '{}'.", method.lexeme);
+            return Err(RuntimeError::new(method, &message));
+        };
+        Ok(Value::Callable(Rc::new(method.bind(instance))))
+    }
+
+    fn evaluate_unary(&mut self, operator: &Token, expr: &Expression) -> RuntimeResult<Value> {
         let right = self.evaluate(expr)?;
-        match right {
-            ObjectValue::Number(v) => match operator.token_type {
-                TokenType::Minus => Some(ObjectValue::Number(-v)),
-                _ => None,
-            },
-            ObjectValue::Boolean(v) => match operator.token_type {
-                TokenType::Bang => Some(ObjectValue::Boolean(!v)),
-                _ => None,
-            },
-            _ => None,
+        match (operator.token_type, right) {
+            (TokenType::Bang, right) => Ok(Value::Boolean(!right.is_truthy())),
+            (TokenType::Minus, Value::Number(v)) => Ok(Value::Number(-v)),
+            _ => Err(RuntimeError::new(operator, "Operand must be a number.")
+                .with_span(operator.span.to(expr.span()))),
         }
     }
 
     pub fn evaluate_binary(
-        &self,
+        &mut self,
         left: &Expression,
         operator: &Token,
         right: &Expression,
-    ) -> Option<ObjectValue> {
-        let left_value = self.evaluate(left)?;
-        let right_value = self.evaluate(right)?;
-
-        if !Interpreter::are_compatible(operator, &left_value, &right_value) {
-            eprintln!(
-                "Incompatible types for operator {}: {:?}, {:?}",
-                operator.token_type, left_value, right_value
-            );
-            return None;
-        }
+    ) -> RuntimeResult<Value> {
+        let span = left.span().to(right.span());
+        let left = self.evaluate(left)?;
+        let right = self.evaluate(right)?;
 
-        match (operator.token_type, left_value, right_value) {
-            (TokenType::Minus, ObjectValue::Number(left), ObjectValue::Number(right)) => {
-                Some(ObjectValue::Number(left - right))
+        let result = match (operator.token_type, &left, &right) {
+            // Equality is defined for every pair of values, including callables.
+            (TokenType::BangEqual, ..) => Value::Boolean(left != right),
+            (TokenType::EqualEqual, ..) => Value::Boolean(left == right),
+            (TokenType::Minus, Value::Number(left), Value::Number(right)) => {
+                Value::Number(left - right)
             },
-            (TokenType::Slash, ObjectValue::Number(left), ObjectValue::Number(right)) => {
-                Some(ObjectValue::Number(left / right))
+            (TokenType::Slash, Value::Number(left), Value::Number(right)) => {
+                Value::Number(left / right)
             },
-            (TokenType::Star, ObjectValue::Number(left), ObjectValue::Number(right)) => {
-                Some(ObjectValue::Number(left * right))
+            (TokenType::Star, Value::Number(left), Value::Number(right)) => {
+                Value::Number(left * right)
             },
-            (TokenType::Plus, ObjectValue::Number(left), ObjectValue::Number(right)) => {
-                Some(ObjectValue::Number(left + right))
+            (TokenType::Plus, Value::Number(left), Value::Number(right)) => {
+                Value::Number(left + right)
             },
-            (TokenType::Plus, ObjectValue::String(left), ObjectValue::String(right)) => {
-                Some(ObjectValue::String([left, right].concat()))
+            (TokenType::Plus, Value::String(left), Value::String(right)) => {
+                Value::String(Symbol::intern(&[&**left, &**right].concat()))
             },
-            (TokenType::Greater, ObjectValue::Number(left), ObjectValue::Number(right)) => {
-                Some(ObjectValue::Boolean(left > right))
+            (TokenType::Greater, Value::Number(left), Value::Number(right)) => {
+                Value::Boolean(left > right)
             },
-            (TokenType::GreaterEqual, ObjectValue::Number(left), ObjectValue::Number(right)) => {
-                Some(ObjectValue::Boolean(left >= right))
+            (TokenType::GreaterEqual, Value::Number(left), Value::Number(right)) => {
+                Value::Boolean(left >= right)
             },
-            (TokenType::Less, ObjectValue::Number(left), ObjectValue::Number(right)) => {
-                Some(ObjectValue::Boolean(left < right))
+            (TokenType::Less, Value::Number(left), Value::Number(right)) => {
+                Value::Boolean(left < right)
             },
-            (TokenType::LessEqual, ObjectValue::Number(left), ObjectValue::Number(right)) => {
-                Some(ObjectValue::Boolean(left <= right))
+            (TokenType::LessEqual, Value::Number(left), Value::Number(right)) => {
+                Value::Boolean(left <= right)
             },
-            (TokenType::BangEqual, left, right) => Some(ObjectValue::Boolean(left != right)),
-            (TokenType::EqualEqual, left, right) => Some(ObjectValue::Boolean(left == right)),
-            _ => None,
-        }
+            _ => return Err(Interpreter::operands_error(operator).with_span(span)),
+        };
+        Ok(result)
     }
 
-    fn are_compatible(operator: &Token, left: &Literal, right: &Literal) -> bool {
-        match operator.token_type {
-            TokenType::Plus => matches!(
-                (left, right),
-                (Literal::Number(_), Literal::Number(_)) | (Literal::String(_), Literal::String(_))
-            ),
-            TokenType::Minus | TokenType::Slash | TokenType::Star => {
-                matches!((left, right), (Literal::Number(_), Literal::Number(_)))
-            },
-            TokenType::Greater
-            | TokenType::GreaterEqual
-            | TokenType::Less
-            | TokenType::LessEqual => {
-                matches!((left, right), (Literal::Number(_), Literal::Number(_)))
-            },
-            TokenType::BangEqual | TokenType::EqualEqual => true,
-            _ => false,
+    fn operands_error(operator: &Token) -> RuntimeError {
+        let message = match operator.token_type {
+            TokenType::Plus => "Operands must be two numbers or two strings.",
+            _ => "Operands must be numbers.",
+        };
+        RuntimeError::new(operator, message)
+    }
+
+    fn evaluate_call(
+        &mut self,
+        callee: &Expression,
+        paren: &Token,
+        arguments: &[Expression],
+    ) -> RuntimeResult<Value> {
+        let callee = self.evaluate(callee)?;
+        let arguments = arguments
+            .iter()
+            .map(|argument| self.evaluate(argument))
+            .collect::<RuntimeResult<Vec<_>>>()?;
+
+        let function: Rc<dyn LoxCallable> = match callee {
+            Value::Callable(function) => function,
+            Value::Class(class) => class,
+            _ => return Err(RuntimeError::new(paren, "Can only call functions and classes.")),
+        };
+        if arguments.len() != function.arity() {
+            let message =
+                format!("Expected {} arguments but got {}.", function.arity(), arguments.len());
+            return Err(RuntimeError::new(paren, &message));
         }
+        function.call(self, paren, arguments)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::ast::Expression;
-    use crate::interpreter::Interpreter;
-    use crate::lexer::{Literal, Token, TokenType};
+    use crate::ast::{Depth, Expression, Stmt};
+    use crate::interpreter::{Environment, Interpreter};
+    use crate::lexer::{Literal, Span, Token, TokenType};
+    use crate::symbol::Symbol;
+    use crate::test_support::resolve;
+    use crate::value::Value;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Runs `source` in `interpreter`, returning whether it completed without runtime errors.
+    fn run(interpreter: &mut Interpreter, source: &str) -> bool {
+        let (statements, errors) = resolve(source);
+        assert_eq!(errors, Vec::<String>::new());
+        statements.iter().all(|stmt| interpreter.execute(stmt).is_ok())
+    }
+
+    /// Runs `source` in `interpreter`, returning the message of the runtime error it stops with.
+    fn run_error(interpreter: &mut Interpreter, source: &str) -> Option<String> {
+        let (statements, errors) = resolve(source);
+        assert_eq!(errors, Vec::<String>::new());
+        statements.iter().find_map(|stmt| interpreter.execute(stmt).err()).map(|e| e.message)
+    }
+
+    fn get(interpreter: &mut Interpreter, name: &str) -> Option<Value> {
+        let name = Token::new(TokenType::Identifier, name, None, 1);
+        interpreter.evaluate(&Expression::Variable(name, Depth::default())).ok()
+    }
 
     #[test]
     fn interprets_number_addition() {
-        let one = Expression::Literal(Some(Literal::Number(1.0)));
+        let one = Expression::Literal(Some(Literal::Number(1.0)), Span::default());
         let plus = Token::new(TokenType::Plus, "+", None, 1);
-        let two = Expression::Literal(Some(Literal::Number(2.0)));
+        let two = Expression::Literal(Some(Literal::Number(2.0)), Span::default());
         let expr = Expression::Binary(Box::new(one), plus, Box::new(two));
 
-        let interpreter = Interpreter::new();
+        let mut interpreter = Interpreter::new();
         let result = interpreter.evaluate(&expr);
-        assert!(result.is_some());
-        assert_eq!(result.unwrap(), Literal::Number(3.0));
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), Value::Number(3.0));
+    }
+
+    #[test]
+    fn executes_print_statement() {
+        let one = Expression::Literal(Some(Literal::Number(1.0)), Span::default());
+        let stmt = Stmt::Print(one);
+
+        let mut interpreter = Interpreter::new();
+        assert!(interpreter.execute(&stmt).is_ok());
+    }
+
+    #[test]
+    fn looks_up_variables_in_enclosing_environment() {
+        let name = Token::new(TokenType::Identifier, "a", None, 1);
+        let globals = Rc::new(RefCell::new(Environment::new(None)));
+        globals.borrow_mut().define(Symbol::intern("a"), Value::Number(1.0));
+
+        let mut local = Environment::new(Some(globals.clone()));
+        assert_eq!(local.get(&name), Some(Value::Number(1.0)));
+        assert!(local.assign(&name, Value::Number(2.0)).is_some());
+        assert_eq!(globals.borrow().get(&name), Some(Value::Number(2.0)));
+    }
+
+    #[test]
+    fn fails_on_undefined_variable() {
+        let name = Token::new(TokenType::Identifier, "a", None, 1);
+        let mut environment = Environment::new(None);
+        assert!(environment.get(&name).is_none());
+        assert!(environment.assign(&name, Value::Nil).is_none());
+    }
+
+    #[test]
+    fn declares_and_assigns_variables() {
+        let name = Token::new(TokenType::Identifier, "a", None, 1);
+        let declaration = Stmt::Var(name.clone(), None);
+        let two = Expression::Literal(Some(Literal::Number(2.0)), Span::default());
+        let assignment = Expression::Assign(name.clone(), Box::new(two), Depth::default());
+
+        let mut interpreter = Interpreter::new();
+        assert!(interpreter.execute(&declaration).is_ok());
+        assert_eq!(
+            interpreter.evaluate(&Expression::Variable(name.clone(), Depth::default())).ok(),
+            Some(Value::Nil)
+        );
+        assert_eq!(interpreter.evaluate(&assignment).ok(), Some(Value::Number(2.0)));
+        assert_eq!(
+            interpreter.evaluate(&Expression::Variable(name, Depth::default())).ok(),
+            Some(Value::Number(2.0))
+        );
+    }
+
+    #[test]
+    fn shadows_variables_in_nested_blocks() {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            var a = "global";
+            var b = "global";
+            { var a = "outer"; { var a = "inner"; b = a; } }
+        "#;
+        assert!(run(&mut interpreter, source));
+        assert_eq!(get(&mut interpreter, "a"), Some(Value::String("global".into())));
+        assert_eq!(get(&mut interpreter, "b"), Some(Value::String("inner".into())));
+    }
+
+    #[test]
+    fn drops_block_scope_on_exit() {
+        let mut interpreter = Interpreter::new();
+        assert!(run(&mut interpreter, "{ var a = 1; }"));
+        assert!(get(&mut interpreter, "a").is_none());
+    }
+
+    #[test]
+    fn restores_environment_when_block_fails() {
+        let mut interpreter = Interpreter::new();
+        assert!(!run(&mut interpreter, "var a = 1; { var a = 2; { var a = 3; undefined; } }"));
+        assert_eq!(get(&mut interpreter, "a"), Some(Value::Number(1.0)));
+    }
+
+    #[test]
+    fn executes_if_else() {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            var a; var b; var c;
+            if (nil) a = "then"; else a = "else";
+            if (0) b = "then"; else b = "else";
+            // A dangling else belongs to the innermost if.
+            if (true) if (false) c = "inner then"; else c = "inner else";
+        "#;
+        assert!(run(&mut interpreter, source));
+        assert_eq!(get(&mut interpreter, "a"), Some(Value::String("else".into())));
+        assert_eq!(get(&mut interpreter, "b"), Some(Value::String("then".into())));
+        assert_eq!(get(&mut interpreter, "c"), Some(Value::String("inner else".into())));
+    }
+
+    #[test]
+    fn executes_loops() {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            var i = 0;
+            while (i < 5) i = i + 1;
+            var sum = 0;
+            for (var j = 0; j < 4; j = j + 1) sum = sum + j;
+        "#;
+        assert!(run(&mut interpreter, source));
+        assert_eq!(get(&mut interpreter, "i"), Some(Value::Number(5.0)));
+        assert_eq!(get(&mut interpreter, "sum"), Some(Value::Number(6.0)));
+        // The loop variable is scoped to the `for` statement.
+        assert!(get(&mut interpreter, "j").is_none());
+    }
+
+    #[test]
+    fn short_circuits_logical_operators() {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            var a = nil or "yes";
+            var b = 1 and 2;
+            var c = false and undefined;
+            var d = "first" or undefined;
+        "#;
+        assert!(run(&mut interpreter, source));
+        assert_eq!(get(&mut interpreter, "a"), Some(Value::String("yes".into())));
+        assert_eq!(get(&mut interpreter, "b"), Some(Value::Number(2.0)));
+        assert_eq!(get(&mut interpreter, "c"), Some(Value::Boolean(false)));
+        assert_eq!(get(&mut interpreter, "d"), Some(Value::String("first".into())));
+    }
+
+    #[test]
+    fn calls_functions() {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            fun fib(n) {
+                if (n < 2) return n;
+                return fib(n - 2) + fib(n - 1);
+            }
+            fun noReturn() {}
+            var a = fib(10);
+            var b = noReturn();
+        "#;
+        assert!(run(&mut interpreter, source));
+        assert_eq!(get(&mut interpreter, "a"), Some(Value::Number(55.0)));
+        assert_eq!(get(&mut interpreter, "b"), Some(Value::Nil));
+        let fib = get(&mut interpreter, "fib").unwrap();
+        assert_eq!(fib.to_string(), "<fn fib>");
+    }
+
+    #[test]
+    fn returns_from_nested_loops_and_blocks() {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            fun find(limit) {
+                for (var i = 0; ; i = i + 1) {
+                    while (true) {
+                        { if (i == limit) return i; }
+                        i = i + 1;
+                    }
+                }
+            }
+            var a = find(3);
+        "#;
+        assert!(run(&mut interpreter, source));
+        assert_eq!(get(&mut interpreter, "a"), Some(Value::Number(3.0)));
+    }
+
+    #[test]
+    fn fails_on_arity_mismatch() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(
+            run_error(&mut interpreter, "fun add(a, b) { return a + b; } add(1, 2, 3);"),
+            Some("Expected 2 arguments but got 3.".to_string())
+        );
+        assert_eq!(
+            run_error(&mut interpreter, "\"not a function\"();"),
+            Some("Can only call functions and classes.".to_string())
+        );
+    }
+
+    #[test]
+    fn closes_over_declaring_environment() {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            fun makeCounter() {
+                var i = 0;
+                fun count() {
+                    i = i + 1;
+                    return i;
+                }
+                return count;
+            }
+            var first = makeCounter();
+            var second = makeCounter();
+            first();
+            var a = first();
+            var b = second();
+        "#;
+        assert!(run(&mut interpreter, source));
+        // Each call to `makeCounter` creates a fresh `i` that outlives the call.
+        assert_eq!(get(&mut interpreter, "a"), Some(Value::Number(2.0)));
+        assert_eq!(get(&mut interpreter, "b"), Some(Value::Number(1.0)));
+    }
+
+    #[test]
+    fn closes_over_block_scope() {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            var callback;
+            {
+                var message = "from block";
+                fun show() { return message; }
+                callback = show;
+            }
+            var a = callback();
+        "#;
+        assert!(run(&mut interpreter, source));
+        assert_eq!(get(&mut interpreter, "a"), Some(Value::String("from block".into())));
+    }
+
+    #[test]
+    fn binds_closures_to_resolved_scope() {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            var a = "global";
+            var first; var second;
+            {
+                fun show() { return a; }
+                first = show();
+                var a = "block";
+                second = show();
+            }
+        "#;
+        assert!(run(&mut interpreter, source));
+        // A later declaration in the block must not change what `show` refers to.
+        assert_eq!(get(&mut interpreter, "first"), Some(Value::String("global".into())));
+        assert_eq!(get(&mut interpreter, "second"), Some(Value::String("global".into())));
+    }
+
+    #[test]
+    fn creates_instances_with_fields_and_methods() {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            class Point {
+                init(x, y) {
+                    this.x = x;
+                    this.y = y;
+                }
+                sum() { return this.x + this.y; }
+            }
+            var point = Point(1, 2);
+            point.y = 5;
+            var sum = point.sum();
+            var method = point.sum;
+            point.x = 10;
+            var bound = method();
+        "#;
+        assert!(run(&mut interpreter, source));
+        assert_eq!(get(&mut interpreter, "sum"), Some(Value::Number(6.0)));
+        // A method extracted from an instance stays bound to it.
+        assert_eq!(get(&mut interpreter, "bound"), Some(Value::Number(15.0)));
+        let point = get(&mut interpreter, "point").unwrap();
+        assert_eq!(point.to_string(), "Point instance");
+        let class = get(&mut interpreter, "Point").unwrap();
+        assert_eq!(class.to_string(), "Point");
+    }
+
+    #[test]
+    fn returns_this_from_initializer() {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            class Foo {
+                init() {
+                    this.calls = 0;
+                    return;
+                }
+            }
+            var foo = Foo();
+            var again = foo.init();
+        "#;
+        assert!(run(&mut interpreter, source));
+        assert_eq!(get(&mut interpreter, "again"), get(&mut interpreter, "foo"));
+    }
+
+    #[test]
+    fn fails_on_invalid_property_access() {
+        let mut interpreter = Interpreter::new();
+        let mut error = |source| run_error(&mut interpreter, source);
+        assert_eq!(error("class A {} A().missing;").unwrap(), "Undefined property 'missing'.");
+        assert_eq!(error("var a = 1; a.field;").unwrap(), "Only instances have properties.");
+        assert_eq!(error("var a = \"str\"; a.field = 1;").unwrap(), "Only instances have fields.");
+        assert_eq!(
+            error("class B { init(a) {} } B();").unwrap(),
+            "Expected 1 arguments but got 0."
+        );
+    }
+
+    #[test]
+    fn inherits_and_calls_super_methods() {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            class A {
+                method() { return "A method"; }
+                name() { return "A"; }
+            }
+            class B < A {
+                method() { return "B method"; }
+                test() { return super.method(); }
+            }
+            class C < B {}
+            var inherited = C().name();
+            var overridden = C().method();
+            var viaSuper = C().test();
+        "#;
+        assert!(run(&mut interpreter, source));
+        assert_eq!(get(&mut interpreter, "inherited"), Some(Value::String("A".into())));
+        assert_eq!(get(&mut interpreter, "overridden"), Some(Value::String("B method".into())));
+        assert_eq!(get(&mut interpreter, "viaSuper"), Some(Value::String("A method".into())));
+    }
+
+    #[test]
+    fn fails_on_non_class_superclass() {
+        let mut interpreter = Interpreter::new();
+        assert!(!run(&mut interpreter, "var NotAClass = \"nope\"; class A < NotAClass {}"));
+    }
+
+    #[test]
+    fn reports_runtime_error_at_offending_token() {
+        let mut interpreter = Interpreter::new();
+        let name = Token::new(TokenType::Identifier, "missing", None, 3);
+        let error =
+            interpreter.evaluate(&Expression::Variable(name, Depth::default())).unwrap_err();
+        assert_eq!(error.message, "Undefined variable 'missing'.");
+        assert_eq!(error.token.line, 3);
     }
 }
@@ -1,4 +1,6 @@
+use crate::symbol::Symbol;
 use std::fmt::Formatter;
+use std::rc::Rc;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum TokenType {
@@ -98,9 +100,81 @@ impl std::fmt::Display for TokenType {
     }
 }
 
+impl TokenType {
+    /// The text of a punctuation, operator or keyword token, which is the same for every token
+    /// of its type.
+    fn fixed_lexeme(self) -> &'static str {
+        match self {
+            TokenType::LeftParen => "(",
+            TokenType::RightParen => ")",
+            TokenType::LeftBrace => "{",
+            TokenType::RightBrace => "}",
+            TokenType::Comma => ",",
+            TokenType::Dot => ".",
+            TokenType::Minus => "-",
+            TokenType::Plus => "+",
+            TokenType::Semicolon => ";",
+            TokenType::Slash => "/",
+            TokenType::Star => "*",
+            TokenType::Bang => "!",
+            TokenType::BangEqual => "!=",
+            TokenType::Equal => "=",
+            TokenType::EqualEqual => "==",
+            TokenType::Greater => ">",
+            TokenType::GreaterEqual => ">=",
+            TokenType::Less => "<",
+            TokenType::LessEqual => "<=",
+            TokenType::And => "and",
+            TokenType::Class => "class",
+            TokenType::Else => "else",
+            TokenType::False => "false",
+            TokenType::Fun => "fun",
+            TokenType::For => "for",
+            TokenType::If => "if",
+            TokenType::Nil => "nil",
+            TokenType::Or => "or",
+            TokenType::Print => "print",
+            TokenType::Return => "return",
+            TokenType::Super => "super",
+            TokenType::This => "this",
+            TokenType::True => "true",
+            TokenType::Var => "var",
+            TokenType::While => "while",
+            TokenType::Eof => "",
+            TokenType::Identifier | TokenType::String | TokenType::Number => {
+                unreachable!("the text of {} tokens varies", self)
+            },
+        }
+    }
+}
+
+/// The source text of a token.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Lexeme {
+    /// An identifier, or `this` or `super`, interned as they are looked up by name.
+    Name(Symbol),
+    /// A string literal, sharing the interned value without its quotes.
+    String(Symbol),
+    /// A number literal, as written in the source.
+    Number(Rc<str>),
+    /// Any other token.
+    Fixed(&'static str),
+}
+
+impl std::fmt::Display for Lexeme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lexeme::Name(name) => write!(f, "{}", name),
+            Lexeme::String(value) => write!(f, "\"{}\"", value),
+            Lexeme::Number(number) => write!(f, "{}", number),
+            Lexeme::Fixed(text) => write!(f, "{}", text),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Literal {
-    String(String),
+    String(Symbol),
     Number(f64),
     Null,
     Boolean(bool),
@@ -117,17 +191,72 @@ impl std::fmt::Display for Literal {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// A region of the source code, measured in bytes.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Span {
+    /// Byte offset of the first character.
+    pub offset: usize,
+    pub len: usize,
+    /// 1-based column of the first character on its line.
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(offset: usize, len: usize, column: usize) -> Self {
+        Self { offset, len, column }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        let (first, last) = if self.offset <= other.offset { (self, other) } else { (other, self) };
+        let end = (first.offset + first.len).max(last.offset + last.len);
+        Span::new(first.offset, end - first.offset, first.column)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Token {
     pub(crate) token_type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Lexeme,
     pub(crate) literal: Option<Literal>,
     pub line: usize,
+    pub span: Span,
 }
 
 impl Token {
     pub fn new(token_type: TokenType, lexeme: &str, literal: Option<Literal>, line: usize) -> Self {
-        Self { token_type, lexeme: lexeme.to_string(), literal, line }
+        let lexeme = match (token_type, &literal) {
+            (TokenType::Identifier, _) => Lexeme::Name(Symbol::intern(lexeme)),
+            (TokenType::This, _) => Lexeme::Name(Symbol::this()),
+            (TokenType::Super, _) => Lexeme::Name(Symbol::super_()),
+            (TokenType::String, Some(Literal::String(value))) => Lexeme::String(value.clone()),
+            (TokenType::String, _) => unreachable!("string tokens carry their value"),
+            (TokenType::Number, _) => Lexeme::Number(lexeme.into()),
+            _ => Lexeme::Fixed(token_type.fixed_lexeme()),
+        };
+        Self { token_type, lexeme, literal, line, span: Span::default() }
+    }
+
+    /// The name an identifier, `this` or `super` token refers to.
+    pub fn symbol(&self) -> &Symbol {
+        match &self.lexeme {
+            Lexeme::Name(name) => name,
+            _ => unreachable!("only identifiers, `this` and `super` name anything"),
+        }
+    }
+
+    pub fn with_span(self, span: Span) -> Self {
+        Self { span, ..self }
+    }
+}
+
+/// Spans are positional metadata, so tokens compare equal regardless of where they were scanned.
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.token_type == other.token_type
+            && self.lexeme == other.lexeme
+            && self.literal == other.literal
+            && self.line == other.line
     }
 }
 
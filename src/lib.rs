@@ -1,9 +1,27 @@
 mod ast;
+mod bytecode;
+mod callable;
+mod chunk;
+mod class;
+mod compiler;
+pub mod diagnostic;
+mod disassembler;
+mod gc;
 mod interpreter;
 mod lexer;
 pub mod lox;
+pub mod native;
+mod object;
+pub mod output;
 mod parser;
+pub mod repl;
 mod reporter;
+mod resolver;
 mod scanner;
+mod symbol;
+#[cfg(test)]
+mod test_support;
+pub mod value;
+mod vm;
 
 pub type Result<T> = anyhow::Result<T>;
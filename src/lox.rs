@@ -1,60 +1,431 @@
-use crate::ast::{AstPrinter, ObjectValue};
+use crate::ast::{AstPrinter, Expression};
+use crate::bytecode;
+pub use crate::bytecode::{BytecodeError, FORMAT_VERSION};
+use crate::compiler::Compiler;
+use crate::diagnostic::{Diagnostic, Phase};
+use crate::disassembler::disassemble;
+pub use crate::gc::GcConfig;
 use crate::interpreter::Interpreter;
-use crate::lexer::Token;
+use crate::lexer::{Token, TokenType};
+use crate::native::NativeFunction;
+use crate::object::ObjFunction;
+use crate::output::{Buffer, Output};
 use crate::parser::Parser;
+pub use crate::reporter::ReportFormat;
 use crate::reporter::{Reporter, SharedReporter};
+use crate::resolver::Resolver;
 use crate::scanner::Scanner;
+use crate::value::Value;
+use crate::vm::Vm;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
 
+/// The maximum depth of nested function calls before reporting a stack overflow, on either
+/// backend.
+pub(crate) const MAX_CALL_DEPTH: usize = 1023;
+
+/// The engine running programs. Both produce the same output.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Backend {
+    /// Walks the syntax tree.
+    #[default]
+    Interpreter,
+    /// Compiles to bytecode first, then runs it on a stack-based virtual machine.
+    Vm,
+}
+
+/// Settings of a [`Lox`] session.
+#[derive(Clone)]
+pub struct Config {
+    /// How errors are rendered.
+    pub format: ReportFormat,
+    pub backend: Backend,
+    /// Print the VM's stack and each instruction as it runs, with [`Backend::Vm`].
+    pub trace: bool,
+    /// When the VM collects garbage, with [`Backend::Vm`] or [`Lox::run_bytecode`]. Ignored by
+    /// the interpreter, which doesn't collect garbage, see [`GcConfig`].
+    pub gc: GcConfig,
+    /// Where the output of `print` is written, stdout by default.
+    pub output: Output,
+    /// Where errors are written, stderr by default.
+    pub errors: Output,
+    /// Collect the output of `print` for [`Lox::take_output`] instead of writing it to `output`.
+    pub capture_output: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            format: ReportFormat::default(),
+            backend: Backend::default(),
+            trace: false,
+            gc: GcConfig::default(),
+            output: Output::stdout(),
+            errors: Output::stderr(),
+            capture_output: false,
+        }
+    }
+}
+
+/// Why a piece of source code could not be run to completion.
+#[derive(Debug)]
+pub enum LoxError {
+    /// Scan, parse or resolution errors. None of the code ran.
+    Compile(Vec<Diagnostic>),
+    Runtime(Box<Diagnostic>),
+    /// A compiled file that could not be loaded. None of the code ran.
+    Bytecode(BytecodeError),
+}
+
+impl LoxError {
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self {
+            LoxError::Compile(diagnostics) => diagnostics,
+            LoxError::Runtime(diagnostic) => std::slice::from_ref(diagnostic.as_ref()),
+            LoxError::Bytecode(_) => &[],
+        }
+    }
+
+    /// The conventional process exit status, as used by the CLI.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LoxError::Compile(_) | LoxError::Bytecode(_) => 65,
+            LoxError::Runtime(_) => 70,
+        }
+    }
+}
+
+impl Display for LoxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let LoxError::Bytecode(error) = self {
+            return write!(f, "{}", error);
+        }
+        let rendered: Vec<String> =
+            self.diagnostics().iter().map(|diagnostic| diagnostic.render_plain()).collect();
+        write!(f, "{}", rendered.join("\n"))
+    }
+}
+
+impl std::error::Error for LoxError {}
+
+/// An embeddable interpreter session. Globals, including native functions, persist from one
+/// call to the next.
 pub struct Lox {
-    pub reporter: SharedReporter,
-    pub tokens: Vec<Token>,
+    config: Config,
+    interpreter: Interpreter,
+    /// Runs programs instead of `interpreter` with [`Backend::Vm`].
+    vm: Vm,
+    /// Where `print` writes to: `config.output`, or the capture buffer.
+    output: Output,
+    /// The output of `print`, when capturing.
+    captured: Option<Buffer>,
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Lox {
-    pub fn new(source: &str) -> Self {
+    pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        let mut interpreter = Interpreter::new();
+        let mut vm = Vm::new();
+        let captured = config.capture_output.then(Buffer::new);
+        let output = match &captured {
+            Some(buffer) => buffer.clone().into(),
+            None => config.output.clone(),
+        };
+        interpreter.set_output(output.clone());
+        vm.set_output(output.clone());
+        vm.set_trace(config.trace);
+        vm.set_gc_config(config.gc);
+        Self { config, interpreter, vm, output, captured }
+    }
+
+    /// Runs `source`, which is either a program or a single expression without a trailing `;`.
+    /// Returns the value of the expression, or nil for a program.
+    pub fn eval_str(&mut self, source: &str) -> Result<Value, LoxError> {
+        Ok(self.eval_source(source)?.unwrap_or(Value::Nil))
+    }
+
+    /// Like [`Lox::eval_str`], but tells a program apart from an expression evaluating to nil.
+    pub(crate) fn eval_source(&mut self, source: &str) -> Result<Option<Value>, LoxError> {
+        let reporter = self.reporter(source);
+        let tokens = Lox::scan(source, &reporter);
+        if !Lox::is_expression(&tokens) {
+            return self.execute(&tokens, &reporter).map(|_| None);
+        }
+
+        let expr = Parser::new(&tokens, &reporter).parse_expression();
+        Lox::check(&reporter)?;
+        let Some(expr) = expr else {
+            // The parser reports why it gave up, but a failure must never go unreported.
+            if let Some(eof) = tokens.last() {
+                let diagnostic = Diagnostic::at(eof, Phase::Parse, "E0100", "Expect expression.");
+                reporter.borrow_mut().report(diagnostic);
+            }
+            return Lox::check(&reporter).map(|_| None);
+        };
+        self.evaluate_expression(&expr, &reporter).map(Some)
+    }
+
+    /// Scans `source`, returning every token, including those after an error, and the errors.
+    pub fn tokenize(&self, source: &str) -> (Vec<Token>, Vec<Diagnostic>) {
+        let reporter = self.reporter(source);
+        let tokens = Lox::scan(source, &reporter);
+        let diagnostics = reporter.borrow().diagnostics.clone();
+        (tokens, diagnostics)
+    }
+
+    /// Parses `source` as an expression and prints its syntax tree.
+    pub fn parse(&self, source: &str) -> Result<String, LoxError> {
+        let reporter = self.reporter(source);
+        let tokens = Lox::scan(source, &reporter);
+        let expr = Parser::new(&tokens, &reporter).parse();
+        Lox::check(&reporter)?;
+        Ok(AstPrinter::new().print(&expr))
+    }
+
+    /// Evaluates `source` as a single expression.
+    pub fn evaluate(&mut self, source: &str) -> Result<Value, LoxError> {
+        let reporter = self.reporter(source);
+        let tokens = Lox::scan(source, &reporter);
+        let expr = Parser::new(&tokens, &reporter).parse();
+        Lox::check(&reporter)?;
+        self.evaluate_expression(&expr, &reporter)
+    }
+
+    /// Compiles `source` as a program and lists the resulting bytecode, see [`Backend::Vm`].
+    pub fn disassemble(&self, source: &str) -> Result<String, LoxError> {
+        Ok(disassemble(&*self.compile_script(source)?))
+    }
+
+    /// Compiles `source` as a program into the binary format run by [`Lox::run_bytecode`].
+    pub fn compile(&self, source: &str) -> Result<Vec<u8>, LoxError> {
+        Ok(bytecode::serialize(&*self.compile_script(source)?))
+    }
+
+    /// Runs a program compiled by [`Lox::compile`]. It always runs on the VM, whatever the
+    /// configured backend.
+    pub fn run_bytecode(&mut self, bytes: &[u8]) -> Result<(), LoxError> {
+        let script = bytecode::deserialize(bytes).map_err(|error| {
+            self.config.errors.write_line(&error.to_string());
+            LoxError::Bytecode(error)
+        })?;
+        // There is no source to quote, so errors are reported by line only.
         let reporter = Reporter::shared();
-        let mut scanner = Scanner::new(source, reporter);
-        scanner.scan_tokens();
-        Self { reporter: scanner.reporter, tokens: scanner.tokens }
+        reporter.borrow_mut().set_format(self.config.format);
+        reporter.borrow_mut().set_output(self.config.errors.clone());
+        let result = self.run_compiled(script, &reporter).map(|_| ());
+        self.output.flush();
+        result
+    }
+
+    /// Runs `source` as a program.
+    pub fn run(&mut self, source: &str) -> Result<(), LoxError> {
+        let reporter = self.reporter(source);
+        let tokens = Lox::scan(source, &reporter);
+        self.execute(&tokens, &reporter)
     }
 
-    pub fn run(&mut self) -> Option<String> {
-        let mut parser = Parser::new(&self.tokens, &self.reporter);
-        let expr = parser.parse();
-        if self.had_error() {
-            None
-        } else {
-            let printer = AstPrinter::new();
-            Some(printer.print(&expr))
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        match self.config.backend {
+            Backend::Interpreter => self.interpreter.get_global(name),
+            Backend::Vm => self.vm.get_global(name),
         }
     }
 
-    pub fn evaluate(&mut self) -> Option<ObjectValue> {
-        let mut parser = Parser::new(&self.tokens, &self.reporter);
-        let expr = parser.parse();
-        if !self.had_error() {
-            let interpreter = Interpreter::new();
-            return interpreter.evaluate(&expr);
+    /// Defines, or redefines, a global variable visible to scripts.
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        match self.config.backend {
+            Backend::Interpreter => self.interpreter.define_global(name, value),
+            Backend::Vm => self.vm.define_global(name, value),
+        }
+    }
+
+    /// Writes `text` where `print` statements write to, e.g. for the REPL to echo values.
+    pub(crate) fn print(&self, text: &str) {
+        self.output.write_line(text);
+        self.output.flush();
+    }
+
+    pub(crate) fn global_names(&self) -> Vec<String> {
+        match self.config.backend {
+            Backend::Interpreter => self.interpreter.global_names(),
+            Backend::Vm => self.vm.global_names(),
+        }
+    }
+
+    /// Exposes a Rust function to scripts as a global called `name`. The function receives
+    /// exactly `arity` arguments, and an `Err` message is raised as a Lox runtime error.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        let native = NativeFunction::new(name, arity, function);
+        self.set_global(name, Value::Callable(Rc::new(native)));
+    }
+
+    /// Returns and clears the output of `print` captured so far, see [`Config::capture_output`].
+    pub fn take_output(&mut self) -> String {
+        self.captured.as_ref().map(Buffer::take).unwrap_or_default()
+    }
+
+    fn reporter(&self, source: &str) -> SharedReporter {
+        let reporter = Reporter::shared();
+        reporter.borrow_mut().set_format(self.config.format);
+        reporter.borrow_mut().set_source(source);
+        reporter.borrow_mut().set_output(self.config.errors.clone());
+        reporter
+    }
+
+    fn compile_script(&self, source: &str) -> Result<Rc<ObjFunction>, LoxError> {
+        let reporter = self.reporter(source);
+        let tokens = Lox::scan(source, &reporter);
+        let statements = Parser::new(&tokens, &reporter).parse_program();
+        Lox::check(&reporter)?;
+        Resolver::new(&reporter).resolve(&statements);
+        Lox::check(&reporter)?;
+        let script = Compiler::new(&reporter).compile(&statements);
+        Lox::check(&reporter)?;
+        Ok(script)
+    }
+
+    /// Scans `source`. Errors are only checked for after parsing, so that syntax errors are
+    /// reported alongside any scanning errors.
+    fn scan(source: &str, reporter: &SharedReporter) -> Vec<Token> {
+        let mut scanner = Scanner::new(source, reporter.clone());
+        scanner.scan_tokens();
+        scanner.tokens
+    }
+
+    fn execute(&mut self, tokens: &[Token], reporter: &SharedReporter) -> Result<(), LoxError> {
+        let statements = Parser::new(tokens, reporter).parse_program();
+        Lox::check(reporter)?;
+        Resolver::new(reporter).resolve(&statements);
+        Lox::check(reporter)?;
+        let result = match self.config.backend {
+            Backend::Interpreter => self
+                .interpreter
+                .interpret(&statements)
+                .map_err(|error| self.runtime_error(reporter, (&error).into())),
+            Backend::Vm => {
+                let script = Compiler::new(reporter).compile(&statements);
+                self.run_compiled(script, reporter).map(|_| ())
+            },
+        };
+        self.output.flush();
+        result
+    }
+
+    /// Resolves and evaluates a parsed expression.
+    fn evaluate_expression(
+        &mut self,
+        expr: &Expression,
+        reporter: &SharedReporter,
+    ) -> Result<Value, LoxError> {
+        Resolver::new(reporter).resolve_expression(expr);
+        Lox::check(reporter)?;
+        let result = match self.config.backend {
+            Backend::Interpreter => self
+                .interpreter
+                .evaluate(expr)
+                .map_err(|error| self.runtime_error(reporter, (&error).into())),
+            Backend::Vm => {
+                let script = Compiler::new(reporter).compile_expression(expr);
+                self.run_compiled(script, reporter)
+            },
+        };
+        self.output.flush();
+        result
+    }
+
+    /// Runs a compiled script on the VM, unless compiling it failed.
+    fn run_compiled(
+        &mut self,
+        script: Rc<ObjFunction>,
+        reporter: &SharedReporter,
+    ) -> Result<Value, LoxError> {
+        Lox::check(reporter)?;
+        self.vm.interpret(script).map_err(|diagnostic| self.runtime_error(reporter, *diagnostic))
+    }
+
+    /// Fails with every error reported so far, if any.
+    fn check(reporter: &SharedReporter) -> Result<(), LoxError> {
+        let reporter = reporter.borrow();
+        if reporter.had_error() {
+            return Err(LoxError::Compile(reporter.diagnostics.clone()));
         }
-        None
+        Ok(())
     }
 
-    pub fn had_error(&self) -> bool {
-        !self.reporter.borrow().errors.is_empty()
+    /// Reports a runtime error, after the output of the program up to the error.
+    fn runtime_error(&self, reporter: &SharedReporter, diagnostic: Diagnostic) -> LoxError {
+        self.output.flush();
+        reporter.borrow_mut().report(diagnostic.clone());
+        LoxError::Runtime(Box::new(diagnostic))
+    }
+
+    /// Whether the tokens look like an expression rather than statements, i.e. they neither
+    /// start with a statement keyword nor end with a `;` or `}`.
+    fn is_expression(tokens: &[Token]) -> bool {
+        let Some(first) = tokens.first() else {
+            return false;
+        };
+        let starts_statement = matches!(
+            first.token_type,
+            TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::Print
+                | TokenType::Return
+                | TokenType::While
+                | TokenType::LeftBrace
+                | TokenType::Eof
+        );
+        let last = tokens.iter().rev().find(|token| token.token_type != TokenType::Eof);
+        let ends_statement = last.is_some_and(|token| {
+            matches!(token.token_type, TokenType::Semicolon | TokenType::RightBrace)
+        });
+        !starts_statement && !ends_statement
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::lexer::{Literal, Token, TokenType};
-    use crate::lox::Lox;
+    use crate::diagnostic::Phase;
+    use crate::lexer::{Token, TokenType};
+    use crate::lox::{Backend, BytecodeError, Config, GcConfig, Lox, LoxError};
+    use crate::output::{Buffer, Output};
+    use crate::symbol::Symbol;
+    use crate::value::Value;
+    use std::io::BufWriter;
+    use std::rc::Rc;
+
+    fn errors(result: Result<(), LoxError>) -> Vec<String> {
+        let error = result.expect_err("expected the program to fail");
+        error.diagnostics().iter().map(|diagnostic| diagnostic.render_plain()).collect()
+    }
+
+    fn capturing() -> Lox {
+        Lox::with_config(Config { capture_output: true, ..Config::default() })
+    }
 
     #[test]
     fn lox_tokenizes() {
-        let source = "true";
-        let lox = Lox::new(source);
+        let (tokens, errors) = Lox::new().tokenize("true");
+        assert!(errors.is_empty());
         assert_eq!(
-            lox.tokens,
+            tokens,
             vec![
                 // Token::new(TokenType::True, "true", Some(Literal::Boolean(true)), 1), // TODO: Fix?
                 Token::new(TokenType::True, "true", None, 1),
@@ -65,37 +436,285 @@ mod test {
 
     #[test]
     fn lox_evaluates_unary_boolean() {
-        let source = "!true";
-        let mut lox = Lox::new(source);
-        let result = lox.evaluate();
-        assert!(result.is_some());
-        assert_eq!(result.unwrap(), Literal::Boolean(false));
+        assert_eq!(Lox::new().evaluate("!true").unwrap(), Value::Boolean(false));
     }
 
     #[test]
     fn lox_evaluates_binary_number() {
-        let source = "2+3";
-        let mut lox = Lox::new(source);
-        let result = lox.evaluate();
-        assert!(result.is_some());
-        assert_eq!(result.unwrap(), Literal::Number(5.0));
+        assert_eq!(Lox::new().evaluate("2+3").unwrap(), Value::Number(5.0));
     }
 
     #[test]
     fn lox_evaluates_nested() {
-        let source = "(2/3)+(2*3)";
-        let mut lox = Lox::new(source);
-        let result = lox.evaluate();
-        assert!(result.is_some());
-        assert_eq!(result.unwrap(), Literal::Number(6.666666666666667));
+        assert_eq!(Lox::new().evaluate("(2/3)+(2*3)").unwrap(), Value::Number(6.666666666666667));
     }
 
     #[test]
     fn lox_evaluates_strings() {
-        let source = "\"hello\" + \" world\"";
-        let mut lox = Lox::new(source);
-        let result = lox.evaluate();
-        assert!(result.is_some());
-        assert_eq!(result.unwrap(), Literal::String("hello world".to_string()));
+        let result = Lox::new().evaluate("\"hello\" + \" world\"");
+        assert_eq!(result.unwrap(), Value::String("hello world".into()));
+    }
+
+    #[test]
+    fn lox_parses_expressions() {
+        assert_eq!(Lox::new().parse("-(1 + nil)").unwrap(), "(- (group (+ 1.0 nil)))");
+        assert!(Lox::new().parse("(1").is_err());
+    }
+
+    #[test]
+    fn lox_runs_program() {
+        let mut lox = capturing();
+        assert!(lox.run("print \"hello\";\n1 + 2;").is_ok());
+        assert_eq!(lox.take_output(), "hello\n");
+        assert_eq!(lox.take_output(), "");
+    }
+
+    #[test]
+    fn lox_writes_to_configured_outputs() {
+        let (output, errors) = (Buffer::new(), Buffer::new());
+        let config = Config {
+            output: output.clone().into(),
+            errors: errors.clone().into(),
+            ..Config::default()
+        };
+        let mut lox = Lox::with_config(config);
+        assert!(lox.run("print 1;\nprint \"two\";").is_ok());
+        assert!(lox.run("print 3;\nprint -nil;").is_err());
+        assert!(lox.run("print;").is_err());
+        assert_eq!(output.take(), "1\ntwo\n3\n");
+        assert_eq!(
+            errors.take(),
+            "Operand must be a number.\n[line 2]\n[line 1] Error at ';': Expect expression.\n"
+        );
+        assert_eq!(lox.take_output(), "");
+    }
+
+    #[test]
+    fn lox_flushes_buffered_output_before_errors_and_after_each_run() {
+        let shared = Buffer::new();
+        let config = Config {
+            output: Output::new(BufWriter::new(shared.clone())),
+            errors: shared.clone().into(),
+            ..Config::default()
+        };
+        let mut lox = Lox::with_config(config);
+        assert!(lox.run("print 1;\nprint -nil;").is_err());
+        assert_eq!(shared.take(), "1\nOperand must be a number.\n[line 2]\n");
+        assert_eq!(lox.eval_str("print 2;").unwrap(), Value::Nil);
+        assert_eq!(shared.take(), "2\n");
+    }
+
+    #[test]
+    fn lox_reports_missing_semicolon() {
+        assert_eq!(
+            errors(Lox::new().run("print 1")),
+            vec!["[line 1] Error at the end: Expect ';' after value."]
+        );
+    }
+
+    #[test]
+    fn lox_exposes_structured_diagnostics() {
+        let Err(LoxError::Compile(diagnostics)) = Lox::new().run("var a = @;\nprint a;") else {
+            panic!("expected compile errors");
+        };
+        let summary: Vec<_> = diagnostics.iter().map(|d| (d.phase, d.code, d.line)).collect();
+        assert_eq!(summary, vec![(Phase::Scan, "E0001", 1), (Phase::Parse, "E0100", 1)]);
+
+        let Err(LoxError::Compile(diagnostics)) = Lox::new().run("var a = ;\nprint a") else {
+            panic!("expected compile errors");
+        };
+        let summary: Vec<_> = diagnostics.iter().map(|d| (d.phase, d.code, d.line)).collect();
+        assert_eq!(summary, vec![(Phase::Parse, "E0100", 1), (Phase::Parse, "E0100", 2)]);
+        assert_eq!(diagnostics[0].lexeme.as_deref(), Some(";"));
+
+        let Err(LoxError::Runtime(diagnostic)) = Lox::new().run("print -nil;") else {
+            panic!("expected a runtime error");
+        };
+        assert_eq!(
+            diagnostic.render_json(),
+            concat!(
+                r#"{"severity":"error","phase":"runtime","code":"E0300","line":1,"column":7,"#,
+                r#""offset":6,"length":4,"message":"Operand must be a number.","notes":[]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn lox_renders_rich_errors() {
+        let source = "fun f() {\n  print 1;\n";
+        let Err(error) = Lox::new().run(source) else { panic!("expected compile errors") };
+        assert_eq!(
+            error.diagnostics()[0].render_rich(Some(source)),
+            [
+                "error: Expect '}' after block.",
+                " --> line 3, column 1",
+                "  |",
+                "3 | ",
+                "  | ^",
+                "  = note: to match '{' at line 1, column 9"
+            ]
+            .join("\n")
+        );
+
+        let source = "print 1 +\n  \"a\";";
+        let Err(error) = Lox::new().run(source) else { panic!("expected a runtime error") };
+        assert_eq!(
+            error.diagnostics()[0].render_rich(Some(source)),
+            "error: Operands must be two numbers or two strings.\n --> line 1, column 7\n  |\n1 | print 1 +\n  |       ^^^"
+        );
+    }
+
+    #[test]
+    fn lox_calls_host_functions() {
+        let mut lox = Lox::new();
+        lox.define_native("add", 2, |arguments| match arguments {
+            [Value::Number(a), Value::Number(b)] => Ok(Value::Number(a + b)),
+            _ => Err("Arguments must be numbers.".to_string()),
+        });
+        assert_eq!(lox.evaluate("add(1, 2) + 3").unwrap(), Value::Number(6.0));
+        assert_eq!(
+            errors(lox.run("var a = 1;\nprint add(a,\n nil);")),
+            vec!["Arguments must be numbers.\n[line 3]"]
+        );
+    }
+
+    #[test]
+    fn lox_keeps_session_state() {
+        let mut lox = capturing();
+        lox.set_global("greeting", Value::String("hi".into()));
+        assert_eq!(lox.eval_str("var count = 1;").unwrap(), Value::Nil);
+        assert_eq!(
+            lox.eval_str("fun bump() { count = count + 1; return count; }").unwrap(),
+            Value::Nil
+        );
+        assert_eq!(lox.eval_str("bump()").unwrap(), Value::Number(2.0));
+        assert_eq!(lox.eval_str("print greeting + \"!\";").unwrap(), Value::Nil);
+        assert_eq!(lox.get_global("count"), Some(Value::Number(2.0)));
+        assert_eq!(lox.get_global("missing"), None);
+        assert_eq!(lox.take_output(), "hi!\n");
+
+        // Errors leave the session usable.
+        assert!(matches!(lox.eval_str("count +"), Err(LoxError::Compile(_))));
+        assert!(matches!(lox.eval_str("count + nil"), Err(LoxError::Runtime(_))));
+        assert_eq!(lox.eval_str("count").unwrap(), Value::Number(2.0));
+    }
+
+    #[test]
+    fn lox_keeps_session_state_on_vm() {
+        let config = Config { backend: Backend::Vm, capture_output: true, ..Config::default() };
+        let mut lox = Lox::with_config(config);
+        lox.define_native("twice", 1, |arguments| match arguments {
+            [Value::Number(n)] => Ok(Value::Number(n * 2.0)),
+            _ => Err("Argument must be a number.".to_string()),
+        });
+        assert_eq!(lox.eval_str("var count = twice(1);").unwrap(), Value::Nil);
+        assert_eq!(lox.eval_str("fun bump() { count = count + 1; }").unwrap(), Value::Nil);
+        assert_eq!(lox.eval_str("bump(); print count;").unwrap(), Value::Nil);
+        assert_eq!(lox.eval_str("count * 10").unwrap(), Value::Number(30.0));
+        assert_eq!(lox.get_global("count"), Some(Value::Number(3.0)));
+        assert_eq!(lox.evaluate("bump").unwrap().to_string(), "<fn bump>");
+        assert_eq!(lox.take_output(), "3\n");
+
+        assert_eq!(
+            errors(lox.run("print twice(\n\"a\");")),
+            vec!["Argument must be a number.\n[line 2]"]
+        );
+        assert_eq!(lox.eval_str("count").unwrap(), Value::Number(3.0));
+    }
+
+    #[test]
+    fn lox_keeps_captured_variables_after_a_runtime_error_on_vm() {
+        let mut lox = Lox::with_config(Config { backend: Backend::Vm, ..Config::default() });
+        lox.eval_str("var f; var h;").unwrap();
+        let failing = "{\n  var p = 0; var x = 1;\n  fun g() { return x; }\n  \
+                       fun set(value) { x = value; }\n  f = g; h = set;\n  nil + 1;\n}";
+        assert!(matches!(lox.eval_str(failing), Err(LoxError::Runtime(_))));
+        assert_eq!(lox.eval_str("f()").unwrap(), Value::Number(1.0));
+        lox.eval_str("h(2);").unwrap();
+        assert_eq!(lox.eval_str("f()").unwrap(), Value::Number(2.0));
+    }
+
+    #[test]
+    fn lox_keeps_values_held_by_the_host_across_collections() {
+        let gc = GcConfig { stress: true, ..GcConfig::default() };
+        let config = Config { backend: Backend::Vm, gc, ..Config::default() };
+        let mut lox = Lox::with_config(config);
+        lox.eval_str("class A {\n  init() { this.x = 1; }\n  get() { return this.x; }\n}").unwrap();
+        let instance = lox.eval_str("A()").unwrap();
+        let method = lox.eval_str("A().get").unwrap();
+        lox.eval_str("var garbage = A(); garbage = nil;").unwrap();
+
+        lox.set_global("h", instance);
+        lox.set_global("m", method);
+        assert_eq!(lox.eval_str("h.x").unwrap(), Value::Number(1.0));
+        assert_eq!(lox.eval_str("m()").unwrap(), Value::Number(1.0));
+    }
+
+    #[test]
+    fn lox_interns_concatenated_strings() {
+        for backend in [Backend::Interpreter, Backend::Vm] {
+            let mut lox = Lox::with_config(Config { backend, ..Config::default() });
+            let Value::String(result) = lox.eval_str("\"a\" + \"b\"").unwrap() else {
+                panic!("expected a string");
+            };
+            assert!(Rc::ptr_eq(result.as_rc(), Symbol::intern("ab").as_rc()));
+        }
+    }
+
+    #[test]
+    fn lox_runs_compiled_bytecode() {
+        let bytes = Lox::new().compile("fun f(a) { return a + 1; }\nprint f(1);\nf(nil);").unwrap();
+        let errors = Buffer::new();
+        let config =
+            Config { capture_output: true, errors: errors.clone().into(), ..Config::default() };
+        let mut lox = Lox::with_config(config);
+        let error = lox.run_bytecode(&bytes).unwrap_err();
+        assert_eq!(lox.take_output(), "2\n");
+        assert_eq!(error.to_string(), "Operands must be two numbers or two strings.\n[line 1]");
+        assert_eq!(errors.take(), "Operands must be two numbers or two strings.\n[line 1]\n");
+
+        let error = lox.run_bytecode(&bytes[..10]).unwrap_err();
+        assert!(matches!(error, LoxError::Bytecode(BytecodeError::Corrupt(_))));
+        assert_eq!(error.exit_code(), 65);
+        assert_eq!(errors.take(), "Corrupt compiled Lox file: unexpected end of file.\n");
+    }
+
+    #[test]
+    fn lox_evaluates_nil() {
+        assert_eq!(Lox::new().evaluate("nil").unwrap().to_string(), "nil");
+    }
+
+    #[test]
+    fn lox_reports_invalid_assignment_target() {
+        assert_eq!(
+            errors(Lox::new().run("var a = 1;\na + 1 = 2;")),
+            vec!["[line 2] Error at '=': Invalid assignment target."]
+        );
+    }
+
+    #[test]
+    fn lox_reports_resolution_errors() {
+        assert_eq!(
+            errors(Lox::new().run("return;")),
+            vec!["[line 1] Error at 'return': Can't return from top-level code."]
+        );
+    }
+
+    #[test]
+    fn lox_reports_runtime_errors() {
+        let error = Lox::new().evaluate("-\"muffin\"").unwrap_err();
+        assert_eq!(error.to_string(), "Operand must be a number.\n[line 1]");
+        assert_eq!(error.exit_code(), 70);
+
+        let error = Lox::new().evaluate("1 +\n\"a\"").unwrap_err();
+        assert_eq!(error.to_string(), "Operands must be two numbers or two strings.\n[line 1]");
+    }
+
+    #[test]
+    fn lox_stops_running_at_runtime_error() {
+        let mut lox = capturing();
+        let result = lox.run("var a = 1;\nprint a;\nprint a * nil;\nprint undefined;");
+        assert_eq!(errors(result), vec!["Operands must be numbers.\n[line 3]"]);
+        assert_eq!(lox.take_output(), "1\n");
     }
 }
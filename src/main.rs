@@ -1,56 +1,168 @@
-use codecrafters_interpreter::lox::Lox;
+use codecrafters_interpreter::lox::{Backend, Config, GcConfig, Lox, ReportFormat};
+use codecrafters_interpreter::repl::Repl;
+use std::path::Path;
 use std::{env, fs};
 
+/// The tree-walking interpreter recurses on the native stack for every Lox call, so it needs far
+/// more than the main thread's default to reach the call depth limit.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        eprintln!("Usage: {} tokenize <filename>", args[0]);
+    let cli = std::thread::Builder::new().stack_size(STACK_SIZE).spawn(run_cli);
+    if cli.expect("failed to start the interpreter thread").join().is_err() {
+        std::process::exit(70);
+    }
+}
+
+fn run_cli() {
+    let (options, args): (Vec<String>, Vec<String>) =
+        normalize_options(env::args()).partition(|arg| arg.starts_with("--"));
+
+    let mut format = ReportFormat::Plain;
+    let mut backend = Backend::Interpreter;
+    let mut trace = false;
+    let mut gc = GcConfig::default();
+    // The first option tuning the garbage collector, which only the VM has.
+    let mut gc_option = None;
+    let mut output = None;
+    for option in &options {
+        if let Some(path) = option.strip_prefix("--output=") {
+            output = Some(path.to_string());
+            continue;
+        }
+        if let Some(bytes) = option.strip_prefix("--gc-threshold=") {
+            gc.initial_threshold = parse_count(option, bytes);
+            gc_option.get_or_insert(option);
+            continue;
+        }
+        if let Some(factor) = option.strip_prefix("--gc-growth=") {
+            gc.growth_factor = parse_count(option, factor);
+            gc_option.get_or_insert(option);
+            continue;
+        }
+        match option.as_str() {
+            "--format=plain" => format = ReportFormat::Plain,
+            "--format=rich" => format = ReportFormat::Rich,
+            "--format=json" => format = ReportFormat::Json,
+            "--backend=interpreter" => backend = Backend::Interpreter,
+            "--backend=vm" => backend = Backend::Vm,
+            "--trace" => trace = true,
+            "--help" => {
+                println!("{}", usage(&args[0]));
+                return;
+            },
+            "--gc-stress" => {
+                gc.stress = true;
+                gc_option.get_or_insert(option);
+            },
+            _ => {
+                eprintln!("Unknown option: {}", option);
+                std::process::exit(64);
+            },
+        }
+    }
+
+    let config = Config { format, backend, trace, gc, ..Config::default() };
+    // Compiled files always run on the VM.
+    let runs_compiled = args.len() > 2
+        && args[1] == "run"
+        && Path::new(&args[2]).extension().is_some_and(|ext| ext == "loxc");
+    if let Some(option) = gc_option {
+        if backend == Backend::Interpreter && !runs_compiled {
+            eprintln!("{} requires --backend=vm: the interpreter doesn't collect garbage", option);
+            std::process::exit(64);
+        }
+    }
+    if args.len() == 1 || args[1] == "repl" {
+        if let Err(error) = Repl::with_config(config).run() {
+            eprintln!("{}", error);
+            std::process::exit(74);
+        }
         return;
     }
+    if args.len() < 3 {
+        eprintln!("{}", usage(&args[0]));
+        std::process::exit(64);
+    }
 
     let command = &args[1];
     let filename = &args[2];
 
-    match command.as_str() {
+    let mut lox = Lox::with_config(config);
+    if runs_compiled {
+        let bytes = fs::read(filename).unwrap();
+        if let Err(error) = lox.run_bytecode(&bytes) {
+            std::process::exit(error.exit_code());
+        }
+        return;
+    }
+
+    let file = fs::read_to_string(filename).unwrap();
+    let result = match command.as_str() {
         "tokenize" => {
-            let file = fs::read_to_string(filename).unwrap();
-            let lox = Lox::new(&file);
-            for token in &lox.tokens {
+            let (tokens, errors) = lox.tokenize(&file);
+            for token in &tokens {
                 println!("{}", token);
             }
-            if lox.had_error() {
+            if !errors.is_empty() {
                 std::process::exit(65);
             }
+            Ok(())
         },
-        "parse" => {
-            let file = fs::read_to_string(filename).unwrap();
-            let mut lox = Lox::new(&file);
-            let result = lox.run().unwrap_or("".to_string());
-            if lox.had_error() {
-                std::process::exit(65);
-            }
-            println!("{}", result);
-        },
-        "run" => {
-            let file = fs::read_to_string(filename).unwrap();
-            let mut lox = Lox::new(&file);
-            let result = lox.run().unwrap();
-            if lox.had_error() {
-                std::process::exit(65);
-            }
-            println!("< {}", result);
-        },
-        "evaluate" => {
-            let file = fs::read_to_string(filename).unwrap();
-            let mut lox = Lox::new(&file);
-            if lox.had_error() {
-                std::process::exit(65);
-            }
-            let result = lox.evaluate().map(|l| l.to_string()).unwrap_or("".to_string());
-            println!("{}", result);
-        },
+        "parse" => lox.parse(&file).map(|ast| println!("{}", ast)),
+        "run" => lox.run(&file),
+        "evaluate" => lox.evaluate(&file).map(|value| println!("{}", value)),
+        "disassemble" => lox.disassemble(&file).map(|listing| print!("{}", listing)),
+        "compile" => lox.compile(&file).map(|bytes| {
+            let output = output.unwrap_or_else(|| {
+                Path::new(filename).with_extension("loxc").to_string_lossy().into_owned()
+            });
+            fs::write(output, bytes).unwrap();
+        }),
         _ => {
             eprintln!("Unknown command: {}", command);
+            std::process::exit(64);
         },
+    };
+    // Errors have already been reported on stderr.
+    if let Err(error) = result {
+        std::process::exit(error.exit_code());
     }
 }
+
+fn usage(program: &str) -> String {
+    format!(
+        "Usage: {program} [--format=plain|rich|json] [--backend interpreter|vm] [--trace] \
+         [--gc-stress] [--gc-threshold=<bytes>] [--gc-growth=<factor>] [repl | <command> <filename> | compile <filename> -o <output.loxc>]\n\
+         The --gc-* options tune the VM's garbage collector, and require --backend=vm except to \
+         run a .loxc file."
+    )
+}
+
+/// The value of a numeric option, exiting if it isn't a non-negative integer.
+fn parse_count(option: &str, value: &str) -> usize {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("Invalid option: {}", option);
+        std::process::exit(64);
+    })
+}
+
+/// Joins options taking a separate value, such as `--backend vm`, into `--backend=vm`, and
+/// spells out `-o <path>` as `--output=<path>`.
+fn normalize_options(args: impl Iterator<Item = String>) -> impl Iterator<Item = String> {
+    let mut args = args.peekable();
+    std::iter::from_fn(move || {
+        let arg = args.next()?;
+        if arg == "-o" {
+            if let Some(path) = args.next() {
+                return Some(format!("--output={path}"));
+            }
+        }
+        if arg == "--backend" {
+            if let Some(value) = args.next_if(|value| !value.starts_with("--")) {
+                return Some(format!("{arg}={value}"));
+            }
+        }
+        Some(arg)
+    })
+}
@@ -0,0 +1,89 @@
+use crate::callable::LoxCallable;
+use crate::interpreter::{Interpreter, RuntimeError, RuntimeResult};
+use crate::lexer::Token;
+use crate::value::Value;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The Rust side of a native function. An `Err` message is raised as a runtime error at the call.
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, String>;
+
+/// A function implemented in Rust and callable from Lox code.
+pub struct NativeFunction {
+    name: String,
+    arity: usize,
+    function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) -> Self {
+        Self { name: name.to_string(), arity, function: Box::new(function) }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Runs the Rust function. `arguments` must already have been checked against the arity.
+    pub fn apply(&self, arguments: &[Value]) -> Result<Value, String> {
+        (self.function)(arguments)
+    }
+}
+
+impl LoxCallable for NativeFunction {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(
+        self: Rc<Self>,
+        _: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Value>,
+    ) -> RuntimeResult<Value> {
+        self.apply(&arguments).map_err(|message| RuntimeError::new(paren, &message))
+    }
+
+    fn as_native(&self) -> Option<&NativeFunction> {
+        Some(self)
+    }
+}
+
+impl Display for NativeFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+/// The native functions every interpreter starts with.
+pub fn builtins() -> Vec<NativeFunction> {
+    vec![NativeFunction::new("clock", 0, |_| {
+        let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| e.to_string())?;
+        Ok(Value::Number(elapsed.as_secs_f64()))
+    })]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lox::Lox;
+    use crate::value::Value;
+
+    #[test]
+    fn clock_returns_seconds() {
+        let mut lox = Lox::new();
+        let result = lox.evaluate("clock() > 1000000000 and clock() < 100000000000");
+        assert_eq!(result.unwrap(), Value::Boolean(true));
+        assert_eq!(lox.evaluate("clock").unwrap().to_string(), "<native fn>");
+    }
+
+    #[test]
+    fn clock_checks_arity() {
+        let error = Lox::new().evaluate("clock(1)").unwrap_err();
+        assert_eq!(error.to_string(), "Expected 0 arguments but got 1.\n[line 1]");
+    }
+}
@@ -0,0 +1,99 @@
+use crate::chunk::Chunk;
+use crate::symbol::{Symbol, SymbolMap};
+use crate::value::Value;
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// A function compiled to bytecode, shared by every closure created from it.
+#[derive(Default)]
+pub struct ObjFunction {
+    /// Empty for the top-level script.
+    pub name: String,
+    pub arity: usize,
+    /// The number of variables captured from enclosing functions.
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
+
+impl Display for ObjFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.name.as_str() {
+            "" => write!(f, "<script>"),
+            name => write!(f, "<fn {}>", name),
+        }
+    }
+}
+
+/// A variable captured by a closure. It stays on the VM stack while the function declaring it
+/// runs, and moves into the upvalue when that function returns.
+pub enum Upvalue {
+    /// The stack slot holding the variable.
+    Open(usize),
+    Closed(Value),
+}
+
+/// A function value created at runtime, along with the variables it captured.
+pub struct ObjClosure {
+    pub function: Rc<ObjFunction>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl Display for ObjClosure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
+
+/// A class created by the VM. Methods are added one by one after the class is created, and
+/// inherited ones are copied down from the superclass.
+pub struct ObjClass {
+    pub name: String,
+    pub methods: RefCell<SymbolMap<Rc<ObjClosure>>>,
+}
+
+impl ObjClass {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string(), methods: RefCell::new(SymbolMap::default()) }
+    }
+
+    pub fn find_method(&self, name: &Symbol) -> Option<Rc<ObjClosure>> {
+        self.methods.borrow().get(name).cloned()
+    }
+}
+
+impl Display for ObjClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// An instance of an [`ObjClass`], holding its own fields.
+pub struct ObjInstance {
+    pub class: Rc<ObjClass>,
+    pub fields: SymbolMap<Value>,
+}
+
+impl ObjInstance {
+    pub fn new(class: Rc<ObjClass>) -> Self {
+        Self { class, fields: SymbolMap::default() }
+    }
+}
+
+impl Display for ObjInstance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
+
+/// A method looked up on an instance, remembering the instance to bind `this` to.
+pub struct ObjBoundMethod {
+    pub receiver: Value,
+    pub method: Rc<ObjClosure>,
+}
+
+impl Display for ObjBoundMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.method)
+    }
+}
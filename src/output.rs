@@ -0,0 +1,88 @@
+use std::cell::RefCell;
+use std::io::{self, BufWriter, Write};
+use std::rc::Rc;
+
+/// Where text is written: the process's stdout or stderr, or any writer supplied by the host.
+///
+/// Clones share the same writer, so one sink can receive the output of several sessions.
+#[derive(Clone)]
+pub struct Output {
+    writer: Rc<RefCell<dyn Write>>,
+}
+
+impl Output {
+    pub fn new(writer: impl Write + 'static) -> Self {
+        Self { writer: Rc::new(RefCell::new(writer)) }
+    }
+
+    /// The process's stdout, buffered so that a program printing in a loop doesn't make a system
+    /// call per line. Call [`Output::flush`] once the output should be visible.
+    pub fn stdout() -> Self {
+        Self::new(BufWriter::new(io::stdout()))
+    }
+
+    pub fn stderr() -> Self {
+        Self::new(io::stderr())
+    }
+
+    /// Writes `text` followed by a newline. Failing to write, e.g. to a closed pipe, is not an
+    /// error of the program being run, so it is ignored.
+    pub fn write_line(&self, text: &str) {
+        let _ = writeln!(self.writer.borrow_mut(), "{}", text);
+    }
+
+    /// Writes out anything still buffered, ignoring failures like [`Output::write_line`] does.
+    pub fn flush(&self) {
+        let _ = self.writer.borrow_mut().flush();
+    }
+}
+
+/// An in-memory writer, for capturing output in tests or in a host application.
+#[derive(Clone, Default)]
+pub struct Buffer {
+    bytes: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Buffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns and clears the text written so far.
+    pub fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.bytes.borrow_mut());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl From<Buffer> for Output {
+    fn from(buffer: Buffer) -> Self {
+        Output::new(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::output::{Buffer, Output};
+
+    #[test]
+    fn shares_buffer_between_clones() {
+        let buffer = Buffer::new();
+        let output = Output::from(buffer.clone());
+        output.clone().write_line("one");
+        output.write_line("two");
+        assert_eq!(buffer.take(), "one\ntwo\n");
+        assert_eq!(buffer.take(), "");
+    }
+}
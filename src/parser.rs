@@ -1,32 +1,301 @@
-use crate::ast::Expression;
+use crate::ast::{Depth, Expression, Function, Stmt};
+use crate::diagnostic::{Diagnostic, Phase};
 use crate::lexer::{Literal, Token, TokenType};
 use crate::reporter::SharedReporter;
 use crate::Result;
 use anyhow::{anyhow, Error};
+use std::rc::Rc;
 
-pub struct Parser {
-    tokens: Vec<Token>,
+/// The maximum number of arguments of a call, and parameters of a function.
+const MAX_ARGUMENTS: usize = 255;
+
+/// How deeply statements and expressions can nest. Every phase after parsing recurses through
+/// the syntax tree, so deeper programs would overflow the native stack.
+const MAX_NESTING: usize = 256;
+
+pub struct Parser<'a> {
+    tokens: &'a [Token],
     current: usize,
     reporter: SharedReporter,
+    /// The number of statements and expressions being parsed that contain the current one.
+    depth: usize,
+    /// Set once nesting gets too deep, to not report the errors it leads to while unwinding,
+    /// until a top-level declaration parses again.
+    too_deep: bool,
 }
 
-impl Parser {
-    pub fn new(tokens: &[Token], reporter: &SharedReporter) -> Self {
-        Self { tokens: tokens.to_vec(), current: 0, reporter: reporter.clone() }
+impl<'a> Parser<'a> {
+    pub fn new(tokens: &'a [Token], reporter: &SharedReporter) -> Self {
+        Self { tokens, current: 0, reporter: reporter.clone(), depth: 0, too_deep: false }
     }
 
     pub fn parse(&mut self) -> Expression {
-        self.expression().unwrap_or(Expression::Literal(None))
+        self.expression().unwrap_or(Expression::Literal(None, self.peek().span))
+    }
+
+    /// Parses a single expression that must span all of the tokens.
+    pub fn parse_expression(&mut self) -> Option<Expression> {
+        let expr = self.expression().ok()?;
+        if !self.is_at_end() {
+            self.error(self.peek(), "Expect end of expression.");
+            return None;
+        }
+        Some(expr)
+    }
+
+    /// Parses a whole program, i.e. a sequence of declarations terminated by `Eof`.
+    ///
+    /// Syntax errors are reported as they are found, and the declarations containing them are
+    /// left out of the returned statements.
+    pub fn parse_program(&mut self) -> Vec<Stmt> {
+        let mut statements = Vec::new();
+        while !self.is_at_end() {
+            statements.extend(self.declaration());
+        }
+        statements
+    }
+
+    /// Parses a declaration, or skips to the start of the next one after a syntax error.
+    fn declaration(&mut self) -> Option<Stmt> {
+        match self.declaration_or_error() {
+            Ok(stmt) => {
+                self.too_deep &= self.depth > 0;
+                Some(stmt)
+            },
+            Err(_) => {
+                self.synchronize();
+                None
+            },
+        }
+    }
+
+    fn declaration_or_error(&mut self) -> Result<Stmt> {
+        if self.matches(&[TokenType::Class]) {
+            return self.class_declaration();
+        }
+        if self.matches(&[TokenType::Fun]) {
+            return Ok(Stmt::Function(self.function("function")?));
+        }
+        if self.matches(&[TokenType::Var]) {
+            return self.var_declaration();
+        }
+        self.statement()
+    }
+
+    fn class_declaration(&mut self) -> Result<Stmt> {
+        self.consume(TokenType::Identifier, "Expect class name.")?;
+        let name = self.previous().clone();
+        let superclass = if self.matches(&[TokenType::Less]) {
+            self.consume(TokenType::Identifier, "Expect superclass name.")?;
+            Some(Expression::Variable(self.previous().clone(), Depth::default()))
+        } else {
+            None
+        };
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+        Ok(Stmt::Class(name, superclass, methods))
+    }
+
+    /// Parses a function's name, parameters and body. `kind` is used in error messages.
+    fn function(&mut self, kind: &str) -> Result<Rc<Function>> {
+        self.consume(TokenType::Identifier, &format!("Expect {kind} name."))?;
+        let name = self.previous().clone();
+        self.consume(TokenType::LeftParen, &format!("Expect '(' after {kind} name."))?;
+        let mut params = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    self.error_with_code(
+                        self.peek(),
+                        "E0102",
+                        "Can't have more than 255 parameters.",
+                    );
+                }
+                self.consume(TokenType::Identifier, "Expect parameter name.")?;
+                params.push(self.previous().clone());
+                if !self.matches(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+
+        let body = self.nested(|parser| {
+            parser.consume(TokenType::LeftBrace, &format!("Expect '{{' before {kind} body."))?;
+            parser.block()
+        })?;
+        Ok(Rc::new(Function { name, params, body }))
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt> {
+        self.consume(TokenType::Identifier, "Expect variable name.")?;
+        let name = self.previous().clone();
+        let initializer =
+            if self.matches(&[TokenType::Equal]) { Some(self.expression()?) } else { None };
+        self.consume(TokenType::Semicolon, "Expect ';' after variable declaration.")?;
+        Ok(Stmt::Var(name, initializer))
+    }
+
+    fn statement(&mut self) -> Result<Stmt> {
+        self.nested(|parser| {
+            if parser.matches(&[TokenType::For]) {
+                return parser.for_statement();
+            }
+            if parser.matches(&[TokenType::If]) {
+                return parser.if_statement();
+            }
+            if parser.matches(&[TokenType::Print]) {
+                return parser.print_statement();
+            }
+            if parser.matches(&[TokenType::Return]) {
+                return parser.return_statement();
+            }
+            if parser.matches(&[TokenType::While]) {
+                return parser.while_statement();
+            }
+            if parser.matches(&[TokenType::LeftBrace]) {
+                return Ok(Stmt::Block(parser.block()?));
+            }
+            parser.expression_statement()
+        })
+    }
+
+    /// Parses the declarations of a block whose `{` has already been consumed.
+    fn block(&mut self) -> Result<Vec<Stmt>> {
+        let opening = self.previous();
+        let mut statements = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            statements.extend(self.declaration());
+        }
+        self.consume_closing(TokenType::RightBrace, "Expect '}' after block.", opening)?;
+        Ok(statements)
+    }
+
+    /// Desugars a `for` loop into an equivalent `while` loop wrapped in blocks.
+    fn for_statement(&mut self) -> Result<Stmt> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+        let initializer = if self.matches(&[TokenType::Semicolon]) {
+            None
+        } else if self.matches(&[TokenType::Var]) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if self.check(TokenType::Semicolon) {
+            Expression::Literal(Some(Literal::Boolean(true)), self.peek().span)
+        } else {
+            self.expression()?
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+
+        let increment =
+            if self.check(TokenType::RightParen) { None } else { Some(self.expression()?) };
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+        let mut body = self.statement()?;
+        if let Some(increment) = increment {
+            body = Stmt::Block(vec![body, Stmt::Expression(increment)]);
+        }
+        body = Stmt::While(condition, Box::new(body));
+        if let Some(initializer) = initializer {
+            body = Stmt::Block(vec![initializer, body]);
+        }
+        Ok(body)
+    }
+
+    /// An `else` binds to the nearest preceding `if`.
+    fn if_statement(&mut self) -> Result<Stmt> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
+
+        let then_branch = self.statement()?;
+        let else_branch =
+            if self.matches(&[TokenType::Else]) { Some(Box::new(self.statement()?)) } else { None };
+        Ok(Stmt::If(condition, Box::new(then_branch), else_branch))
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        let body = self.statement()?;
+        Ok(Stmt::While(condition, Box::new(body)))
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt> {
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+        Ok(Stmt::Print(value))
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous().clone();
+        let value = if self.check(TokenType::Semicolon) { None } else { Some(self.expression()?) };
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(Stmt::Return(keyword, value))
+    }
+
+    fn expression_statement(&mut self) -> Result<Stmt> {
+        let expr = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+        Ok(Stmt::Expression(expr))
     }
 
     fn expression(&mut self) -> Result<Expression> {
-        self.equality()
+        self.nested(Self::assignment)
+    }
+
+    fn assignment(&mut self) -> Result<Expression> {
+        let expr = self.or()?;
+        if self.matches(&[TokenType::Equal]) {
+            let equals = self.previous();
+            let value = self.nested(Self::assignment)?;
+            return match expr {
+                Expression::Variable(name, _) => {
+                    Ok(Expression::Assign(name, Box::new(value), Depth::default()))
+                },
+                Expression::Get(object, name) => Ok(Expression::Set(object, name, Box::new(value))),
+                expr => {
+                    // Report, but don't bail out: the parser isn't in a confused state.
+                    self.error_with_code(equals, "E0101", "Invalid assignment target.");
+                    Ok(expr)
+                },
+            };
+        }
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expression> {
+        let mut expr = self.and()?;
+        while self.matches(&[TokenType::Or]) {
+            let operator = self.previous().clone();
+            let right = self.and()?;
+            expr = Expression::Logical(Box::new(expr), operator, Box::new(right));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expression> {
+        let mut expr = self.equality()?;
+        while self.matches(&[TokenType::And]) {
+            let operator = self.previous().clone();
+            let right = self.equality()?;
+            expr = Expression::Logical(Box::new(expr), operator, Box::new(right));
+        }
+        Ok(expr)
     }
 
     fn equality(&mut self) -> Result<Expression> {
         let mut expr = self.comparison()?;
-        while self.matches(vec![TokenType::BangEqual, TokenType::EqualEqual]) {
-            let operator = self.previous();
+        while self.matches(&[TokenType::BangEqual, TokenType::EqualEqual]) {
+            let operator = self.previous().clone();
             let right = self.comparison()?;
             expr = Expression::Binary(Box::new(expr), operator, Box::new(right));
         }
@@ -35,13 +304,13 @@ impl Parser {
 
     fn comparison(&mut self) -> Result<Expression> {
         let mut expr = self.term()?;
-        while self.matches(vec![
+        while self.matches(&[
             TokenType::Greater,
             TokenType::GreaterEqual,
             TokenType::Less,
             TokenType::LessEqual,
         ]) {
-            let operator = self.previous();
+            let operator = self.previous().clone();
             let right = self.term()?;
             expr = Expression::Binary(Box::new(expr), operator, Box::new(right));
         }
@@ -50,8 +319,8 @@ impl Parser {
 
     fn term(&mut self) -> Result<Expression> {
         let mut expr = self.factor()?;
-        while self.matches(vec![TokenType::Minus, TokenType::Plus]) {
-            let operator = self.previous();
+        while self.matches(&[TokenType::Minus, TokenType::Plus]) {
+            let operator = self.previous().clone();
             let right = self.factor()?;
             expr = Expression::Binary(Box::new(expr), operator, Box::new(right));
         }
@@ -60,8 +329,8 @@ impl Parser {
 
     fn factor(&mut self) -> Result<Expression> {
         let mut expr = self.unary()?;
-        while self.matches(vec![TokenType::Slash, TokenType::Star]) {
-            let operator = self.previous();
+        while self.matches(&[TokenType::Slash, TokenType::Star]) {
+            let operator = self.previous().clone();
             let right = self.unary()?;
             expr = Expression::Binary(Box::new(expr), operator, Box::new(right));
         }
@@ -69,39 +338,104 @@ impl Parser {
     }
 
     fn unary(&mut self) -> Result<Expression> {
-        if self.matches(vec![TokenType::Bang, TokenType::Minus]) {
-            let operator = self.previous();
-            let right = self.unary()?;
+        if self.matches(&[TokenType::Bang, TokenType::Minus]) {
+            let operator = self.previous().clone();
+            let right = self.nested(Self::unary)?;
             return Ok(Expression::Unary(operator, Box::new(right)));
         }
-        self.primary()
+        self.call()
+    }
+
+    fn call(&mut self) -> Result<Expression> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.matches(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.matches(&[TokenType::Dot]) {
+                self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
+                expr = Expression::Get(Box::new(expr), self.previous().clone());
+            } else {
+                break;
+            }
+        }
+        Ok(expr)
+    }
+
+    /// Parses the arguments of a call whose `(` has already been consumed.
+    fn finish_call(&mut self, callee: Expression) -> Result<Expression> {
+        let opening = self.previous();
+        let mut arguments = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if arguments.len() >= MAX_ARGUMENTS {
+                    self.error_with_code(
+                        self.peek(),
+                        "E0102",
+                        "Can't have more than 255 arguments.",
+                    );
+                }
+                arguments.push(self.expression()?);
+                if !self.matches(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume_closing(TokenType::RightParen, "Expect ')' after arguments.", opening)?;
+        let paren = self.previous().clone();
+        Ok(Expression::Call(Box::new(callee), paren, arguments))
     }
 
     fn primary(&mut self) -> Result<Expression> {
-        if self.matches(vec![TokenType::False]) {
-            return Ok(Expression::Literal(Some(Literal::Boolean(false))));
+        if self.matches(&[TokenType::False]) {
+            return Ok(Expression::Literal(Some(Literal::Boolean(false)), self.previous().span));
+        }
+        if self.matches(&[TokenType::True]) {
+            return Ok(Expression::Literal(Some(Literal::Boolean(true)), self.previous().span));
+        }
+        if self.matches(&[TokenType::Nil]) {
+            return Ok(Expression::Literal(Some(Literal::Null), self.previous().span));
+        }
+        if self.matches(&[TokenType::Number, TokenType::String]) {
+            let token = self.previous();
+            return Ok(Expression::Literal(token.literal.clone(), token.span));
         }
-        if self.matches(vec![TokenType::True]) {
-            return Ok(Expression::Literal(Some(Literal::Boolean(true))));
+        if self.matches(&[TokenType::Super]) {
+            let keyword = self.previous().clone();
+            self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+            self.consume(TokenType::Identifier, "Expect superclass method name.")?;
+            return Ok(Expression::Super(keyword, self.previous().clone(), Depth::default()));
         }
-        if self.matches(vec![TokenType::Nil]) {
-            return Ok(Expression::Literal(Some(Literal::String("nil".to_string()))));
+        if self.matches(&[TokenType::This]) {
+            return Ok(Expression::This(self.previous().clone(), Depth::default()));
         }
-        if self.matches(vec![TokenType::Number, TokenType::String]) {
-            return Ok(Expression::Literal(self.previous().literal));
+        if self.matches(&[TokenType::Identifier]) {
+            return Ok(Expression::Variable(self.previous().clone(), Depth::default()));
         }
 
-        if self.matches(vec![TokenType::LeftParen]) {
+        if self.matches(&[TokenType::LeftParen]) {
+            let opening = self.previous();
             let expr = self.expression()?;
-            self.consume(TokenType::RightParen, "Expect ')' after expression.")
-                .map_err(|_| self.synchronize())
-                .unwrap();
-            return Ok(Expression::Grouping(Box::new(expr)));
+            self.consume_closing(TokenType::RightParen, "Expect ')' after expression.", opening)?;
+            let span = opening.span.to(self.previous().span);
+            return Ok(Expression::Grouping(Box::new(expr), span));
         }
 
         Err(self.error(self.peek(), "Expect expression."))
     }
 
+    /// Parses with `parse` one level of nesting deeper, reporting an error past [`MAX_NESTING`].
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth >= MAX_NESTING {
+            let error = self.error_with_code(self.peek(), "E0103", "Too much nesting.");
+            self.too_deep = true;
+            return Err(error);
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
     fn consume(&mut self, token_type: TokenType, message: &str) -> Result<()> {
         if self.check(token_type) {
             self.advance();
@@ -110,39 +444,76 @@ impl Parser {
         Err(self.error(self.peek(), message))
     }
 
-    fn error(&mut self, token: Token, message: &str) -> Error {
-        self.reporter.borrow_mut().error(token, message);
+    /// Like `consume`, but points back at the unmatched `opening` delimiter on failure.
+    fn consume_closing(
+        &mut self,
+        token_type: TokenType,
+        message: &str,
+        opening: &Token,
+    ) -> Result<()> {
+        if self.check(token_type) {
+            self.advance();
+            return Ok(());
+        }
+        let note = format!(
+            "to match '{}' at line {}, column {}",
+            opening.lexeme, opening.line, opening.span.column
+        );
+        let diagnostic = Diagnostic::at(self.peek(), Phase::Parse, "E0100", message);
+        Err(self.report(diagnostic.with_note(&note)))
+    }
+
+    fn error(&mut self, token: &Token, message: &str) -> Error {
+        self.error_with_code(token, "E0100", message)
+    }
+
+    fn error_with_code(&mut self, token: &Token, code: &'static str, message: &str) -> Error {
+        self.report(Diagnostic::at(token, Phase::Parse, code, message))
+    }
+
+    fn report(&mut self, diagnostic: Diagnostic) -> Error {
+        if !self.too_deep {
+            self.reporter.borrow_mut().report(diagnostic);
+        }
         anyhow!("Parser error")
     }
 
-    /// Discard tokens until your each the next statement.
+    /// Discards tokens until the start of the next statement, i.e. just after a `;` or right
+    /// before a keyword beginning a statement, or a `}` closing an enclosing block. Brackets
+    /// opened by the discarded tokens are skipped along with everything inside them.
     fn synchronize(&mut self) {
-        self.advance();
-
+        let mut open = 0usize;
         while !self.is_at_end() {
+            match self.advance().token_type {
+                TokenType::LeftParen | TokenType::LeftBrace => open += 1,
+                TokenType::RightParen | TokenType::RightBrace => open = open.saturating_sub(1),
+                _ => {},
+            }
+            if open > 0 {
+                continue;
+            }
             if self.previous().token_type == TokenType::Semicolon {
                 return;
             }
 
             match self.peek().token_type {
-                TokenType::Class => {},
-                TokenType::Fun => {},
-                TokenType::Var => {},
-                TokenType::For => {},
-                TokenType::If => {},
-                TokenType::While => {},
-                TokenType::Print => {},
-                TokenType::Return => {},
-                _ => {
-                    self.advance();
-                },
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return
+                | TokenType::RightBrace => return,
+                _ => {},
             }
         }
     }
 
     /// Check whether the current token matches any of `token_types`.
-    fn matches(&mut self, token_types: Vec<TokenType>) -> bool {
-        for tt in token_types {
+    fn matches(&mut self, token_types: &[TokenType]) -> bool {
+        for &tt in token_types {
             if self.check(tt) {
                 self.advance();
                 return true;
@@ -160,7 +531,7 @@ impl Parser {
     }
 
     /// Advances `self.current` and consumes the `self.current` Token.
-    fn advance(&mut self) -> Token {
+    fn advance(&mut self) -> &'a Token {
         if !self.is_at_end() {
             self.current += 1;
         }
@@ -172,12 +543,103 @@ impl Parser {
     }
 
     /// Returns the `self.current` token, yet to be consumed.
-    fn peek(&self) -> Token {
-        self.tokens[self.current].clone()
+    fn peek(&self) -> &'a Token {
+        &self.tokens[self.current]
     }
 
     /// Returns most recently consumed `Token`.
-    fn previous(&self) -> Token {
-        self.tokens[self.current - 1].clone()
+    fn previous(&self) -> &'a Token {
+        &self.tokens[self.current - 1]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::{Expression, Stmt};
+    use crate::parser::Parser;
+    use crate::reporter::Reporter;
+    use crate::scanner::Scanner;
+    use crate::test_support::parse;
+    use std::rc::Rc;
+
+    #[test]
+    fn reports_every_syntax_error() {
+        let (statements, errors) = parse("var = 1;\nprint 1;\nprint (2;\nvar b = 3;\nfun () {}");
+        assert_eq!(
+            errors,
+            vec![
+                "[line 1] Error at '=': Expect variable name.",
+                "[line 3] Error at ';': Expect ')' after expression.",
+                "[line 5] Error at '(': Expect function name."
+            ]
+        );
+        // The valid declarations survive.
+        assert_eq!(statements.len(), 2);
+        assert!(matches!(statements[0], Stmt::Print(_)));
+        assert!(matches!(statements[1], Stmt::Var(..)));
+    }
+
+    #[test]
+    fn recovers_inside_blocks() {
+        let (statements, errors) = parse("{\n  print;\n  print 1;\n}\nprint 2;");
+        assert_eq!(errors, vec!["[line 2] Error at ';': Expect expression."]);
+        assert_eq!(statements.len(), 2);
+        let Stmt::Block(block) = &statements[0] else { panic!("expected a block") };
+        assert_eq!(block.len(), 1);
+    }
+
+    #[test]
+    fn does_not_panic_on_truncated_input() {
+        for source in ["(", "print (1", "fun f(", "class A { m(", "if (", "a.", "-", "{ {"] {
+            let (_, errors) = parse(source);
+            assert!(!errors.is_empty(), "expected an error for {source:?}");
+        }
+    }
+
+    #[test]
+    fn borrows_tokens_and_shares_their_names() {
+        let reporter = Reporter::shared();
+        let mut scanner = Scanner::new("a + b", reporter.clone());
+        scanner.scan_tokens();
+        let tokens = &scanner.tokens;
+        let mut parser = Parser::new(tokens, &reporter);
+        assert!(std::ptr::eq(parser.peek(), &tokens[0]));
+        assert!(std::ptr::eq(parser.advance(), &tokens[0]));
+        assert!(std::ptr::eq(parser.previous(), &tokens[0]));
+
+        let Expression::Binary(left, _, right) = Parser::new(tokens, &reporter).parse() else {
+            panic!("expected a binary expression")
+        };
+        for (operand, token) in [(left, &tokens[0]), (right, &tokens[2])] {
+            let Expression::Variable(name, _) = *operand else { panic!("expected a variable") };
+            assert!(Rc::ptr_eq(name.symbol().as_rc(), token.symbol().as_rc()));
+        }
+    }
+
+    #[test]
+    fn limits_nesting() {
+        // Debug builds need more than a test thread's stack to parse up to the limit.
+        let thread = std::thread::Builder::new().stack_size(64 * 1024 * 1024);
+        thread.spawn(parse_deeply_nested_programs).unwrap().join().unwrap();
+    }
+
+    fn parse_deeply_nested_programs() {
+        let deep = [
+            format!("print {}1{};", "(".repeat(100_000), ")".repeat(100_000)),
+            format!("print {}1;", "-".repeat(200_000)),
+            format!("{}{}", "{".repeat(50_000), "}".repeat(50_000)),
+            format!("{}print 1;", "if (true) ".repeat(50_000)),
+            format!("{}{}", "fun f() { ".repeat(20_000), "}".repeat(20_000)),
+        ];
+        for source in deep {
+            let (statements, errors) = parse(&format!("{source}\nprint 2;\nprint;"));
+            assert_eq!(errors.len(), 2, "{errors:?}");
+            assert!(errors[0].ends_with("Too much nesting."), "{errors:?}");
+            assert_eq!(errors[1], "[line 3] Error at ';': Expect expression.");
+            assert!(matches!(statements.last(), Some(Stmt::Print(_))));
+        }
+
+        let (_, errors) = parse(&format!("print {}1{};", "(".repeat(200), ")".repeat(200)));
+        assert!(errors.is_empty(), "{errors:?}");
     }
 }
@@ -0,0 +1,275 @@
+use crate::lox::{Config, Lox};
+use crate::reporter::ReportFormat;
+use crate::scanner::KEYWORDS;
+use crate::value::Value;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::path::PathBuf;
+use std::{env, fs};
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+const HISTORY_FILE: &str = ".lox_history";
+const COMMANDS: [&str; 4] = [":help", ":reset", ":load", ":ast"];
+const HELP: &str = "\
+:help          Show this message.
+:reset         Forget every global defined so far.
+:load <file>   Run a script in this session.
+:ast <expr>    Print the syntax tree of an expression.";
+
+/// An interactive session, keeping globals alive from one input to the next.
+pub struct Repl {
+    lox: Lox,
+    config: Config,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new(ReportFormat::default())
+    }
+}
+
+impl Repl {
+    pub fn new(format: ReportFormat) -> Self {
+        Self::with_config(Config { format, ..Config::default() })
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        Self { lox: Lox::with_config(config.clone()), config }
+    }
+
+    /// Reads inputs from the terminal until end of input, printing the value of bare expressions.
+    /// An input continues over several lines while it has unclosed brackets or strings.
+    ///
+    /// History is kept in `~/.lox_history`, and tab completes keywords, globals and commands.
+    pub fn run(&mut self) -> rustyline::Result<()> {
+        let mut editor = Editor::<LoxHelper, FileHistory>::new()?;
+        editor.set_helper(Some(LoxHelper::default()));
+        let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        if let Some(history) = &history {
+            // There is no history yet on the first run.
+            let _ = editor.load_history(history);
+        }
+
+        let mut source = String::new();
+        loop {
+            if let Some(helper) = editor.helper_mut() {
+                helper.globals = self.lox.global_names();
+            }
+            let prompt = if source.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+            let line = match editor.readline(prompt) {
+                Ok(line) => line,
+                // Ctrl-C abandons the current input, Ctrl-D ends the session.
+                Err(ReadlineError::Interrupted) => {
+                    source.clear();
+                    continue;
+                },
+                Err(ReadlineError::Eof) => break,
+                Err(error) => return Err(error),
+            };
+
+            if source.is_empty() && line.trim_start().starts_with(':') {
+                editor.add_history_entry(line.trim())?;
+                let output = self.command(line.trim());
+                // e.g. `:load` of a script without a trailing expression has nothing to show.
+                if !output.is_empty() {
+                    self.lox.print(&output);
+                }
+                continue;
+            }
+            source.push_str(&line);
+            source.push('\n');
+            if !Repl::is_complete(&source) {
+                continue;
+            }
+            editor.add_history_entry(source.trim_end())?;
+            if let Some(value) = self.eval(&source) {
+                self.lox.print(&value.to_string());
+            }
+            source.clear();
+        }
+
+        if let Some(history) = &history {
+            if let Err(error) = editor.save_history(history) {
+                let message = format!("Could not save history to {}: {error}", history.display());
+                self.config.errors.write_line(&message);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a meta-command such as `:load <file>`, returning what to show the user.
+    pub fn command(&mut self, line: &str) -> String {
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let argument = argument.trim();
+        match command {
+            ":help" => HELP.to_string(),
+            ":reset" => {
+                self.lox = Lox::with_config(self.config.clone());
+                "Session reset.".to_string()
+            },
+            ":load" if !argument.is_empty() => match fs::read_to_string(argument) {
+                Ok(source) => self.eval(&source).map_or(String::new(), |value| value.to_string()),
+                Err(error) => format!("Could not read '{argument}': {error}"),
+            },
+            ":ast" if !argument.is_empty() => self.lox.parse(argument).unwrap_or_default(),
+            ":load" => "Usage: :load <file>".to_string(),
+            ":ast" => "Usage: :ast <expr>".to_string(),
+            _ => format!("Unknown command '{command}'. Type :help for a list of commands."),
+        }
+    }
+
+    /// Runs one input, reporting any errors. Returns the value if the input is a bare expression.
+    pub fn eval(&mut self, source: &str) -> Option<Value> {
+        self.lox.eval_source(source).ok().flatten()
+    }
+
+    /// Whether `source` has no unclosed brackets or strings, skipping over comments.
+    fn is_complete(source: &str) -> bool {
+        let mut depth = 0i32;
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '(' | '{' => depth += 1,
+                ')' | '}' => depth -= 1,
+                '"' if !chars.by_ref().any(|c| c == '"') => return false,
+                '/' if chars.peek() == Some(&'/') => {
+                    chars.by_ref().find(|&c| c == '\n');
+                },
+                _ => {},
+            }
+        }
+        // Stray closing brackets are left for the parser to report.
+        depth <= 0
+    }
+}
+
+/// Completes keywords, globals and meta-commands in the line editor.
+#[derive(Default)]
+struct LoxHelper {
+    /// Globals defined in the session, refreshed before each line is read.
+    globals: Vec<String>,
+}
+
+impl LoxHelper {
+    /// Finds the start of the word ending at `pos`, and the candidates completing it.
+    fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let start =
+            line[..pos].rfind(|c: char| !(c.is_alphanumeric() || c == '_')).map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        if start == 1 && line.starts_with(':') {
+            let commands = COMMANDS.iter().filter(|command| command[1..].starts_with(word));
+            return (0, commands.map(|command| command.to_string()).collect());
+        }
+        if word.is_empty() {
+            return (start, Vec::new());
+        }
+
+        let names = KEYWORDS.keys().map(|keyword| keyword.to_string());
+        let mut candidates: Vec<String> = names
+            .chain(self.globals.iter().cloned())
+            .filter(|name| name.starts_with(word))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        (start, candidates)
+    }
+}
+
+impl Completer for LoxHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LoxHelper {}
+
+impl Validator for LoxHelper {}
+
+impl Helper for LoxHelper {}
+
+#[cfg(test)]
+mod test {
+    use crate::repl::{LoxHelper, Repl};
+    use crate::value::Value;
+
+    #[test]
+    fn keeps_state_between_inputs() {
+        let mut repl = Repl::default();
+        assert_eq!(repl.eval("var a = 1;\n"), None);
+        assert_eq!(repl.eval("fun twice(x) { return x * 2; }\n"), None);
+        assert_eq!(repl.eval("twice(a) + 1\n"), Some(Value::Number(3.0)));
+        assert_eq!(repl.eval("a = \"b\"\n"), Some(Value::String("b".into())));
+    }
+
+    #[test]
+    fn recovers_from_errors() {
+        let mut repl = Repl::default();
+        assert_eq!(repl.eval("var a = 1;\n"), None);
+        assert_eq!(repl.eval("var b = ;\n"), None);
+        assert_eq!(repl.eval("1 2\n"), None);
+        assert_eq!(repl.eval("a + nil\n"), None);
+        assert_eq!(repl.eval("{ var c = a; undefined; }\n"), None);
+        assert_eq!(repl.eval("a\n"), Some(Value::Number(1.0)));
+    }
+
+    #[test]
+    fn detects_incomplete_input() {
+        assert!(Repl::is_complete("print 1;\n"));
+        assert!(Repl::is_complete("print \"(\"; // {\n"));
+        assert!(Repl::is_complete("}\n"));
+        assert!(!Repl::is_complete("fun f() {\n"));
+        assert!(!Repl::is_complete("print (1 +\n"));
+        assert!(!Repl::is_complete("print \"multi\n"));
+    }
+
+    #[test]
+    fn runs_meta_commands() {
+        let mut repl = Repl::default();
+        assert!(repl.command(":help").contains(":load <file>"));
+        assert_eq!(repl.command(":ast 1 + -x"), "(+ 1.0 (- x))");
+        assert_eq!(repl.command(":ast"), "Usage: :ast <expr>");
+        assert_eq!(
+            repl.command(":frobnicate"),
+            "Unknown command ':frobnicate'. Type :help for a list of commands."
+        );
+
+        repl.eval("var a = 1;\n");
+        assert_eq!(repl.command(":reset"), "Session reset.");
+        assert_eq!(repl.eval("a\n"), None);
+
+        let path = std::env::temp_dir().join("repl_runs_meta_commands.lox");
+        std::fs::write(&path, "var loaded = 2;\n").unwrap();
+        assert_eq!(repl.command(&format!(":load {}", path.display())), "");
+        assert_eq!(repl.eval("loaded\n"), Some(Value::Number(2.0)));
+        assert!(repl.command(":load /nonexistent.lox").starts_with("Could not read"));
+    }
+
+    #[test]
+    fn completes_keywords_globals_and_commands() {
+        let helper = LoxHelper { globals: vec!["counter".to_string(), "fib".to_string()] };
+        assert_eq!(helper.candidates("print co", 8), (6, vec!["counter".to_string()]));
+        assert_eq!(
+            helper.candidates("f", 1),
+            (0, vec!["false", "fib", "for", "fun"].into_iter().map(String::from).collect())
+        );
+        assert_eq!(helper.candidates(":l", 2), (0, vec![":load".to_string()]));
+        assert_eq!(helper.candidates("1 + ", 4), (4, Vec::new()));
+    }
+}
@@ -1,9 +1,27 @@
-use crate::lexer::{Token, TokenType};
+use crate::diagnostic::{Diagnostic, Phase};
+use crate::output::Output;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+/// How diagnostics are rendered.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ReportFormat {
+    /// One line per error, e.g. `[line 1] Error at 'x': message`, as expected by codecrafters.
+    #[default]
+    Plain,
+    /// The offending source line underlined with carets, followed by any notes.
+    Rich,
+    /// One JSON object per line, for editors and CI.
+    Json,
+}
+
 pub struct Reporter {
-    pub errors: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
+    format: ReportFormat,
+    /// The source code being reported on, needed to show snippets in rich mode.
+    source: Option<String>,
+    /// Where rendered diagnostics are written to.
+    output: Output,
 }
 
 impl Default for Reporter {
@@ -14,26 +32,95 @@ impl Default for Reporter {
 
 impl Reporter {
     fn new() -> Self {
-        Self { errors: Vec::new() }
+        Self {
+            diagnostics: Vec::new(),
+            format: ReportFormat::default(),
+            source: None,
+            output: Output::stderr(),
+        }
     }
 
     pub fn shared() -> SharedReporter {
         Rc::new(RefCell::new(Reporter::new()))
     }
 
-    pub fn error(&mut self, token: Token, message: &str) {
-        if token.token_type == TokenType::Eof {
-            self.report(token.line, " at the end", message)
-        } else {
-            self.report(token.line, &format!(" at '{}'", token.lexeme), message)
+    pub fn set_format(&mut self, format: ReportFormat) {
+        self.format = format;
+    }
+
+    pub fn set_source(&mut self, source: &str) {
+        self.source = Some(source.to_string());
+    }
+
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        self.output.write_line(&self.render(&diagnostic));
+        self.diagnostics.push(diagnostic)
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        match self.format {
+            ReportFormat::Plain => diagnostic.render_plain(),
+            ReportFormat::Rich => diagnostic.render_rich(self.source.as_deref()),
+            ReportFormat::Json => diagnostic.render_json(),
         }
     }
 
-    pub fn report(&mut self, line: usize, location: &str, message: &str) {
-        let error = format!("[line {line}] Error{location}: {message}");
-        eprintln!("{}", error);
-        self.errors.push(error)
+    /// Whether any scan, parse or resolution errors were reported.
+    pub fn had_error(&self) -> bool {
+        self.diagnostics.iter().any(|diagnostic| diagnostic.phase != Phase::Runtime)
+    }
+
+    /// Rendered scan, parse and resolution errors, for asserting on in tests.
+    #[cfg(test)]
+    pub fn errors(&self) -> Vec<String> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.phase != Phase::Runtime)
+            .map(|diagnostic| self.render(diagnostic))
+            .collect()
     }
 }
 
 pub type SharedReporter = Rc<RefCell<Reporter>>;
+
+#[cfg(test)]
+mod test {
+    use crate::diagnostic::{Diagnostic, Phase};
+    use crate::lexer::Span;
+    use crate::output::Buffer;
+    use crate::reporter::{ReportFormat, Reporter};
+
+    #[test]
+    fn renders_in_selected_format() {
+        let output = Buffer::new();
+        let mut reporter = Reporter::default();
+        reporter.set_output(output.clone().into());
+        reporter.set_source("print $;");
+        reporter.report(Diagnostic::error(
+            Phase::Scan,
+            "E0001",
+            1,
+            Span::new(6, 1, 7),
+            "Unexpected character: $",
+        ));
+        assert_eq!(reporter.errors(), vec!["[line 1] Error: Unexpected character: $"]);
+        assert_eq!(output.take(), "[line 1] Error: Unexpected character: $\n");
+
+        reporter.set_format(ReportFormat::Rich);
+        assert_eq!(
+            reporter.errors(),
+            vec!["error: Unexpected character: $\n --> line 1, column 7\n  |\n1 | print $;\n  |       ^"]
+        );
+
+        reporter.set_format(ReportFormat::Json);
+        assert!(reporter.errors()[0].starts_with(r#"{"severity":"error","phase":"scan""#));
+    }
+}
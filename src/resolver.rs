@@ -0,0 +1,329 @@
+use crate::ast::{Depth, Expression, Function, Stmt, StmtVisitor, Visitor};
+use crate::diagnostic::{Diagnostic, Phase};
+use crate::lexer::Token;
+use crate::reporter::SharedReporter;
+use crate::symbol::{Symbol, SymbolMap};
+
+#[derive(Copy, Clone, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+/// Static pass between parsing and interpretation that binds every variable use to the scope
+/// declaring it, and reports scoping mistakes before any code runs.
+pub struct Resolver {
+    /// Local scopes, innermost last. Each maps a name to whether its initializer has finished.
+    /// Globals are not tracked.
+    scopes: Vec<SymbolMap<bool>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    reporter: SharedReporter,
+}
+
+impl Resolver {
+    pub fn new(reporter: &SharedReporter) -> Self {
+        Self {
+            scopes: Vec::new(),
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            reporter: reporter.clone(),
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            self.visit_stmt(stmt);
+        }
+    }
+
+    pub fn resolve_expression(&mut self, expr: &Expression) {
+        self.visit(expr);
+    }
+
+    fn resolve_function(&mut self, function: &Function, function_type: FunctionType) {
+        let enclosing_function = self.current_function;
+        self.current_function = function_type;
+
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve(&function.body);
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+    }
+
+    /// Records how many scopes out `name` was declared, leaving globals unresolved.
+    fn resolve_local(&mut self, name: &Token, depth: &Depth) {
+        let distance = self.scopes.iter().rev().position(|scope| scope.contains_key(name.symbol()));
+        depth.set(distance);
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(SymbolMap::default());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Adds `name` to the innermost scope, marking it as not ready for use yet.
+    fn declare(&mut self, name: &Token) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        if scope.contains_key(name.symbol()) {
+            self.error(name, "E0200", "Already a variable with this name in this scope.");
+            return;
+        }
+        scope.insert(name.symbol().clone(), false);
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.symbol().clone(), true);
+        }
+    }
+
+    fn error(&mut self, token: &Token, code: &'static str, message: &str) {
+        let diagnostic = Diagnostic::at(token, Phase::Resolve, code, message);
+        self.reporter.borrow_mut().report(diagnostic);
+    }
+}
+
+impl StmtVisitor for Resolver {
+    type Output = ();
+
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Self::Output {
+        match stmt {
+            Stmt::Block(statements) => {
+                self.begin_scope();
+                self.resolve(statements);
+                self.end_scope();
+            },
+            Stmt::Class(name, superclass, methods) => {
+                let enclosing_class = self.current_class;
+                self.current_class = ClassType::Class;
+                self.declare(name);
+                self.define(name);
+
+                if let Some(superclass) = superclass {
+                    if let Expression::Variable(superclass_name, _) = superclass {
+                        if superclass_name.lexeme == name.lexeme {
+                            self.error(
+                                superclass_name,
+                                "E0205",
+                                "A class can't inherit from itself.",
+                            );
+                        }
+                    }
+                    self.current_class = ClassType::Subclass;
+                    self.visit(superclass);
+
+                    // Methods of a subclass close over a scope binding `super` to the superclass.
+                    self.begin_scope();
+                    if let Some(scope) = self.scopes.last_mut() {
+                        scope.insert(Symbol::super_(), true);
+                    }
+                }
+
+                // Methods close over a scope binding `this` to the instance.
+                self.begin_scope();
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(Symbol::this(), true);
+                }
+                for method in methods {
+                    let function_type = if *method.name.symbol() == Symbol::init() {
+                        FunctionType::Initializer
+                    } else {
+                        FunctionType::Method
+                    };
+                    self.resolve_function(method, function_type);
+                }
+                self.end_scope();
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+
+                self.current_class = enclosing_class;
+            },
+            Stmt::Expression(expr) => self.visit(expr),
+            Stmt::Function(function) => {
+                // Defined eagerly so the function can refer to itself recursively.
+                self.declare(&function.name);
+                self.define(&function.name);
+                self.resolve_function(function, FunctionType::Function);
+            },
+            Stmt::If(condition, then_branch, else_branch) => {
+                self.visit(condition);
+                self.visit_stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.visit_stmt(else_branch);
+                }
+            },
+            Stmt::Print(expr) => self.visit(expr),
+            Stmt::Return(keyword, value) => {
+                if self.current_function == FunctionType::None {
+                    self.error(keyword, "E0202", "Can't return from top-level code.");
+                }
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        self.error(keyword, "E0203", "Can't return a value from an initializer.");
+                    }
+                    self.visit(value);
+                }
+            },
+            Stmt::Var(name, initializer) => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.visit(initializer);
+                }
+                self.define(name);
+            },
+            Stmt::While(condition, body) => {
+                self.visit(condition);
+                self.visit_stmt(body);
+            },
+        }
+    }
+}
+
+impl Visitor for Resolver {
+    type Output = ();
+
+    fn visit(&mut self, expr: &Expression) -> Self::Output {
+        match expr {
+            Expression::Assign(name, value, depth) => {
+                self.visit(value);
+                self.resolve_local(name, depth);
+            },
+            Expression::Binary(left, _, right) | Expression::Logical(left, _, right) => {
+                self.visit(left);
+                self.visit(right);
+            },
+            Expression::Call(callee, _, arguments) => {
+                self.visit(callee);
+                for argument in arguments {
+                    self.visit(argument);
+                }
+            },
+            Expression::Get(object, _) => self.visit(object),
+            Expression::Grouping(expr, _) | Expression::Unary(_, expr) => self.visit(expr),
+            Expression::Set(object, _, value) => {
+                self.visit(value);
+                self.visit(object);
+            },
+            Expression::Super(keyword, _, depth) => {
+                match self.current_class {
+                    ClassType::None => {
+                        self.error(keyword, "E0206", "Can't use 'super' outside of a class.");
+                        return;
+                    },
+                    ClassType::Class => {
+                        self.error(
+                            keyword,
+                            "E0207",
+                            "Can't use 'super' in a class with no superclass.",
+                        );
+                        return;
+                    },
+                    ClassType::Subclass => {},
+                }
+                self.resolve_local(keyword, depth);
+            },
+            Expression::This(keyword, depth) => {
+                if self.current_class == ClassType::None {
+                    self.error(keyword, "E0204", "Can't use 'this' outside of a class.");
+                    return;
+                }
+                self.resolve_local(keyword, depth);
+            },
+            Expression::Literal(..) => {},
+            Expression::Variable(name, depth) => {
+                let is_declared_but_undefined =
+                    self.scopes.last().and_then(|scope| scope.get(name.symbol())) == Some(&false);
+                if is_declared_but_undefined {
+                    self.error(name, "E0201", "Can't read local variable in its own initializer.");
+                }
+                self.resolve_local(name, depth);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_support;
+
+    fn resolve(source: &str) -> Vec<String> {
+        test_support::resolve(source).1
+    }
+
+    #[test]
+    fn resolves_valid_program() {
+        let source = "var a = 1; { var b = a; } fun f(b) { { var c = b; return c; } }";
+        assert_eq!(resolve(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_read_in_own_initializer() {
+        assert_eq!(
+            resolve("{ var a = 1; { var a = a; } }"),
+            vec!["[line 1] Error at 'a': Can't read local variable in its own initializer."]
+        );
+    }
+
+    #[test]
+    fn reports_redeclared_local() {
+        assert_eq!(
+            resolve("fun f(a) { var a; }\n{ var b; var b; }"),
+            vec![
+                "[line 1] Error at 'a': Already a variable with this name in this scope.",
+                "[line 2] Error at 'b': Already a variable with this name in this scope."
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_top_level_return() {
+        assert_eq!(
+            resolve("return 1;"),
+            vec!["[line 1] Error at 'return': Can't return from top-level code."]
+        );
+    }
+
+    #[test]
+    fn reports_misplaced_this_and_initializer_return() {
+        assert_eq!(
+            resolve("print this;\nclass A { init() { return 1; } }"),
+            vec![
+                "[line 1] Error at 'this': Can't use 'this' outside of a class.",
+                "[line 2] Error at 'return': Can't return a value from an initializer."
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_invalid_inheritance_and_super() {
+        assert_eq!(
+            resolve("class A < A {}\nsuper.foo();\nclass B { foo() { super.foo(); } }"),
+            vec![
+                "[line 1] Error at 'A': A class can't inherit from itself.",
+                "[line 2] Error at 'super': Can't use 'super' outside of a class.",
+                "[line 3] Error at 'super': Can't use 'super' in a class with no superclass."
+            ]
+        );
+    }
+}
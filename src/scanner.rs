@@ -1,12 +1,14 @@
-use crate::lexer::{Literal, Token, TokenType};
+use crate::diagnostic::{Diagnostic, Phase};
+use crate::lexer::{Literal, Span, Token, TokenType};
 use crate::reporter::SharedReporter;
+use crate::symbol::Symbol;
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use std::str::FromStr;
 use std::string::ToString;
 
 const NULL_C: char = '\0';
-static KEYWORDS: Lazy<HashMap<&'static str, TokenType>> = Lazy::new(|| {
+pub(crate) static KEYWORDS: Lazy<HashMap<&'static str, TokenType>> = Lazy::new(|| {
     let mut m = HashMap::new();
     m.insert("and", TokenType::And);
     m.insert("class", TokenType::Class);
@@ -34,6 +36,10 @@ pub struct Scanner {
     start: usize,
     current: usize,
     line: usize,
+    /// 1-based column of `self.current` on its line, counted in characters.
+    column: usize,
+    /// Column of `self.start`, recorded before a multiline token moves `self.column`.
+    start_column: usize,
 }
 
 impl Scanner {
@@ -46,6 +52,8 @@ impl Scanner {
             current: 0,
             // Source, even if empty, starts at the first line.
             line: 1,
+            column: 1,
+            start_column: 1,
         }
     }
 
@@ -53,28 +61,24 @@ impl Scanner {
     pub fn scan_tokens(&mut self) {
         while !self.is_at_end() {
             self.start = self.current;
+            self.start_column = self.column;
             self.scan_token();
         }
 
-        self.tokens.push(Token::new(TokenType::Eof, "", None, self.line))
+        self.start = self.current;
+        self.start_column = self.column;
+        let eof = Token::new(TokenType::Eof, "", None, self.line).with_span(self.span());
+        self.tokens.push(eof)
     }
 
     /// Scan a single token from `self.source`.
     fn scan_token(&mut self) {
         use crate::lexer::TokenType::*;
 
-        let current = self.current;
-        let char = self.advance();
-        if char.is_none() {
-            self.reporter.borrow_mut().report(
-                self.line,
-                "",
-                &format!("Invalid UTF-8 codepoint at: {}", current),
-            );
+        // Source code is a `str`, so it only ever holds valid characters.
+        let Some(char) = self.advance() else {
             return;
-        }
-
-        let char = char.unwrap();
+        };
         let token_type = match char {
             '(' => Some(LeftParen),
             ')' => Some(RightParen),
@@ -118,11 +122,7 @@ impl Scanner {
                     self.identifier();
                     None
                 } else {
-                    self.reporter.borrow_mut().report(
-                        self.line,
-                        "",
-                        &format!("Unexpected character: {char}"),
-                    );
+                    self.error("E0001", &format!("Unexpected character: {char}"));
                     None
                 }
             },
@@ -181,7 +181,7 @@ impl Scanner {
         }
 
         if self.is_at_end() {
-            self.reporter.borrow_mut().report(self.line, "", "Unterminated string.");
+            self.error("E0002", "Unterminated string.");
             return;
         }
 
@@ -190,39 +190,27 @@ impl Scanner {
 
         // Trim the surrounding quotes.
         let value = &self.source[self.start + 1..self.current - 1];
-        self.add_token(TokenType::String, Some(Literal::String(value.to_string())));
+        self.add_token(TokenType::String, Some(Literal::String(Symbol::intern(value))));
     }
 
     /// Only consume a character in `self.source` if it matches the `expected` character.
     fn match_char(&mut self, expected: char) -> bool {
-        if self.is_at_end() || self.nth(self.current) != expected {
+        if self.peek() != expected {
             false
         } else {
-            self.current += 1;
+            self.advance();
             true
         }
     }
 
     /// Get the next character in `self.source` without consuming it.
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            NULL_C
-        } else {
-            self.nth(self.current)
-        }
+        self.source[self.current..].chars().next().unwrap_or(NULL_C)
     }
 
     /// Get the character after the next character in `self.source` without consuming it.
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            NULL_C
-        } else {
-            self.nth(self.current + 1)
-        }
-    }
-
-    fn nth(&self, n: usize) -> char {
-        char::from(self.source.as_bytes()[n])
+        self.source[self.current..].chars().nth(1).unwrap_or(NULL_C)
     }
 
     fn is_digit(&self, c: char) -> bool {
@@ -233,13 +221,13 @@ impl Scanner {
         self.current >= self.source.len()
     }
 
-    /// Consume the next character in `self.source`.
+    /// Consume the next character in `self.source`. `self.current` stays a byte offset, moving
+    /// past every byte of a multibyte character.
     fn advance(&mut self) -> Option<char> {
-        // The next value in `self.source` may or may not be a valid Unicode codepoint
-        // See: https://users.rust-lang.org/t/should-you-really-use-chars-for-characters-in-a-string/71459/3
-        let char = self.source.chars().nth(self.current);
-        self.current += 1;
-        char
+        let char = self.source[self.current..].chars().next()?;
+        self.current += char.len_utf8();
+        self.column = if char == '\n' { 1 } else { self.column + 1 };
+        Some(char)
     }
 
     fn add_char_token(&mut self, t: TokenType) {
@@ -247,10 +235,20 @@ impl Scanner {
     }
 
     fn add_token(&mut self, t: TokenType, literal: Option<Literal>) {
-        let text = self.source[self.start..self.current].to_string();
-        let token = Token::new(t, &text, literal, self.line);
+        let text = &self.source[self.start..self.current];
+        let token = Token::new(t, text, literal, self.line).with_span(self.span());
         self.tokens.push(token);
     }
+
+    fn error(&mut self, code: &'static str, message: &str) {
+        let diagnostic = Diagnostic::error(Phase::Scan, code, self.line, self.span(), message);
+        self.reporter.borrow_mut().report(diagnostic);
+    }
+
+    /// The span of the token currently being scanned.
+    fn span(&self) -> Span {
+        Span::new(self.start, self.current - self.start, self.start_column)
+    }
 }
 
 #[cfg(test)]
@@ -342,7 +340,7 @@ mod test {
                 Token::new(
                     TokenType::String,
                     "\"hello\nworld\"",
-                    Some(Literal::String("hello\nworld".to_string())),
+                    Some(Literal::String(Symbol::intern("hello\nworld"))),
                     2
                 ),
                 Token::new(TokenType::Eof, "", None, 2)
@@ -404,11 +402,40 @@ mod test {
         let mut scanner = Scanner::new(source, reporter);
         scanner.scan_tokens();
         assert_eq!(
-            scanner.reporter.borrow().errors,
+            scanner.reporter.borrow().errors(),
             vec![
                 "[line 1] Error: Unexpected character: $",
                 "[line 1] Error: Unexpected character: #"
             ]
         );
     }
+
+    #[test]
+    fn scans_non_ascii_strings_and_identifiers() {
+        let source = "print \"h\u{e9}llo\";\n\u{e9}t\u{e9} = 1;";
+        let tokens = scan(source);
+        assert_eq!(tokens[1].literal, Some(Literal::String(Symbol::intern("h\u{e9}llo"))));
+        assert_eq!(tokens[1].span, Span::new(6, 8, 7));
+        assert_eq!(tokens[2].span, Span::new(14, 1, 14));
+        assert_eq!(*tokens[3].symbol(), "\u{e9}t\u{e9}");
+        assert_eq!(tokens[3].span, Span::new(16, 5, 1));
+        assert_eq!(tokens[4].span, Span::new(22, 1, 5));
+    }
+
+    #[test]
+    fn records_token_spans() {
+        let source = "var x\n  = \"a\nb\" ;";
+        let spans: Vec<Span> = scan(source).iter().map(|token| token.span).collect();
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(0, 3, 1),
+                Span::new(4, 1, 5),
+                Span::new(8, 1, 3),
+                Span::new(10, 5, 5),
+                Span::new(16, 1, 4),
+                Span::new(17, 0, 5)
+            ]
+        );
+    }
 }
@@ -0,0 +1,191 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+thread_local! {
+    /// Every string interned on this thread that a symbol still refers to.
+    static INTERNER: RefCell<HashSet<Rc<str>>> = RefCell::new(HashSet::new());
+
+    static THIS: Symbol = Symbol::intern("this");
+    static INIT: Symbol = Symbol::intern("init");
+    static SUPER: Symbol = Symbol::intern("super");
+}
+
+/// An interned string, such as an identifier or a string value.
+///
+/// Equal strings are interned to the same allocation, so symbols are cheap to clone, and are
+/// compared and hashed by pointer rather than by content. A string is removed from the interner
+/// once the last symbol referring to it is dropped.
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+impl Symbol {
+    pub fn intern(s: &str) -> Self {
+        INTERNER.with(|strings| {
+            let mut strings = strings.borrow_mut();
+            if let Some(string) = strings.get(s) {
+                return Symbol(string.clone());
+            }
+            let string: Rc<str> = s.into();
+            strings.insert(string.clone());
+            Symbol(string)
+        })
+    }
+
+    /// The symbol for `s` if it has been interned, without interning it otherwise, e.g. to look
+    /// up a variable by name.
+    pub fn lookup(s: &str) -> Option<Self> {
+        INTERNER.with(|strings| strings.borrow().get(s).cloned().map(Symbol))
+    }
+
+    /// `this`, the receiver of a method.
+    pub fn this() -> Self {
+        THIS.with(Symbol::clone)
+    }
+
+    /// `init`, the name of a class's initializer.
+    pub fn init() -> Self {
+        INIT.with(Symbol::clone)
+    }
+
+    /// `super`, the superclass of the class a method is declared in.
+    pub fn super_() -> Self {
+        SUPER.with(Symbol::clone)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The interned string itself, e.g. for the garbage collector to track it.
+    pub fn as_rc(&self) -> &Rc<str> {
+        &self.0
+    }
+}
+
+impl Drop for Symbol {
+    fn drop(&mut self) {
+        // The interner holds the only other reference once the last symbol is dropped. It may
+        // already be gone if this symbol is dropped while the thread exits.
+        if Rc::strong_count(&self.0) == 2 {
+            let _ = INTERNER.try_with(|strings| {
+                let mut strings = strings.borrow_mut();
+                if strings.get(&*self.0).is_some_and(|string| Rc::ptr_eq(string, &self.0)) {
+                    strings.remove(&*self.0);
+                }
+            });
+        }
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(s: &str) -> Self {
+        Symbol::intern(s)
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Rc::as_ptr(&self.0) as *const u8 as usize).hash(state);
+    }
+}
+
+/// A map keyed by symbols, such as the variables of a scope or the fields of an instance.
+pub type SymbolMap<V> = HashMap<Symbol, V, BuildHasherDefault<SymbolHasher>>;
+
+/// Hashes a symbol's address. Interning already makes addresses unique, so a cheap mix of their
+/// bits is enough, where the default hasher would spend most of a variable lookup.
+#[derive(Default)]
+pub struct SymbolHasher(u64);
+
+impl Hasher for SymbolHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        // Fibonacci hashing, folded so that the low bits used to pick a bucket depend on all of
+        // the address rather than on its alignment.
+        let hash = (self.0 ^ n).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        self.0 = hash ^ (hash >> 32);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::symbol::Symbol;
+    use std::rc::Rc;
+
+    #[test]
+    fn interns_equal_strings_once() {
+        let a = Symbol::intern("name");
+        let b = Symbol::intern(&String::from("name"));
+        assert!(Rc::ptr_eq(a.as_rc(), b.as_rc()));
+        assert_eq!(a, b);
+        assert_ne!(a, Symbol::intern("other"));
+        assert_eq!(a, "name");
+        assert_eq!(a.to_string(), "name");
+    }
+
+    #[test]
+    fn forgets_strings_no_symbol_refers_to() {
+        assert!(Symbol::lookup("forgotten").is_none());
+        let symbol = Symbol::intern("forgotten");
+        assert_eq!(Symbol::lookup("forgotten"), Some(symbol.clone()));
+        drop(symbol);
+        assert!(Symbol::lookup("forgotten").is_none());
+    }
+}
@@ -0,0 +1,54 @@
+//! Fixtures shared by the unit tests of the phases after scanning.
+
+use crate::ast::Stmt;
+use crate::compiler::Compiler;
+use crate::object::ObjFunction;
+use crate::output::Buffer;
+use crate::parser::Parser;
+use crate::reporter::{Reporter, SharedReporter};
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
+use crate::vm::Vm;
+use std::rc::Rc;
+
+fn scan_and_parse(source: &str, reporter: &SharedReporter) -> Vec<Stmt> {
+    reporter.borrow_mut().set_source(source);
+    let mut scanner = Scanner::new(source, reporter.clone());
+    scanner.scan_tokens();
+    Parser::new(&scanner.tokens, reporter).parse_program()
+}
+
+/// Parses `source` as a program, returning the statements and the errors reported.
+pub fn parse(source: &str) -> (Vec<Stmt>, Vec<String>) {
+    let reporter = Reporter::shared();
+    let statements = scan_and_parse(source, &reporter);
+    let errors = reporter.borrow().errors();
+    (statements, errors)
+}
+
+/// Parses and resolves `source` as a program, returning the statements and the errors reported.
+pub fn resolve(source: &str) -> (Vec<Stmt>, Vec<String>) {
+    let reporter = Reporter::shared();
+    let statements = scan_and_parse(source, &reporter);
+    Resolver::new(&reporter).resolve(&statements);
+    let errors = reporter.borrow().errors();
+    (statements, errors)
+}
+
+/// Compiles `source` as a program, which must be free of compile errors.
+pub fn compile(source: &str) -> Rc<ObjFunction> {
+    let reporter = Reporter::shared();
+    let statements = scan_and_parse(source, &reporter);
+    Resolver::new(&reporter).resolve(&statements);
+    let script = Compiler::new(&reporter).compile(&statements);
+    assert_eq!(reporter.borrow().errors(), Vec::<String>::new());
+    script
+}
+
+/// Runs `source` in `vm`, returning what it printed and the runtime error, if any.
+pub fn run(vm: &mut Vm, source: &str) -> (String, Option<String>) {
+    let output = Buffer::new();
+    vm.set_output(output.clone().into());
+    let error = vm.interpret(compile(source)).err().map(|error| error.render_plain());
+    (output.take(), error)
+}
@@ -0,0 +1,141 @@
+use crate::callable::LoxCallable;
+use crate::class::{LoxClass, LoxInstance};
+use crate::lexer::Literal;
+use crate::object::{ObjBoundMethod, ObjClass, ObjClosure, ObjInstance};
+use crate::symbol::Symbol;
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+
+/// An opaque object owned by the host application and handed to Lox code.
+pub trait NativeObject: Display {
+    /// Allows the host to downcast the object back to its concrete type.
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// A value produced and manipulated at runtime.
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    /// Interned, so that equal strings are the same allocation.
+    String(Symbol),
+    Callable(Rc<dyn LoxCallable>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    Native(Rc<dyn NativeObject>),
+    /// A function, method, class or instance of the bytecode VM.
+    Closure(Rc<ObjClosure>),
+    BoundMethod(Rc<ObjBoundMethod>),
+    ObjClass(Rc<ObjClass>),
+    ObjInstance(Rc<RefCell<ObjInstance>>),
+}
+
+impl Value {
+    /// Lox follows Ruby's rule: `false` and `nil` are falsey, everything else is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+}
+
+impl From<&Literal> for Value {
+    fn from(literal: &Literal) -> Self {
+        match literal {
+            Literal::String(s) => Value::String(s.clone()),
+            Literal::Number(n) => Value::Number(*n),
+            Literal::Null => Value::Nil,
+            Literal::Boolean(b) => Value::Boolean(*b),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(left), Value::Boolean(right)) => left == right,
+            (Value::Number(left), Value::Number(right)) => left == right,
+            (Value::String(left), Value::String(right)) => left == right,
+            // Callables and objects are only ever equal to themselves.
+            (Value::Callable(left), Value::Callable(right)) => Rc::ptr_eq(left, right),
+            (Value::Class(left), Value::Class(right)) => Rc::ptr_eq(left, right),
+            (Value::Instance(left), Value::Instance(right)) => Rc::ptr_eq(left, right),
+            (Value::Native(left), Value::Native(right)) => Rc::ptr_eq(left, right),
+            (Value::Closure(left), Value::Closure(right)) => Rc::ptr_eq(left, right),
+            (Value::BoundMethod(left), Value::BoundMethod(right)) => Rc::ptr_eq(left, right),
+            (Value::ObjClass(left), Value::ObjClass(right)) => Rc::ptr_eq(left, right),
+            (Value::ObjInstance(left), Value::ObjInstance(right)) => Rc::ptr_eq(left, right),
+            _ => false,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(b) => write!(f, "{}", b),
+            // Integral numbers print without a fractional part, e.g. `3` rather than `3.0`, and
+            // with every digit however large they are. Infinities are spelled out as in jlox.
+            Value::Number(n) if n.is_infinite() => {
+                write!(f, "{}", if *n > 0.0 { "Infinity" } else { "-Infinity" })
+            },
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Callable(callable) => write!(f, "{}", callable),
+            Value::Class(class) => write!(f, "{}", class),
+            Value::Instance(instance) => write!(f, "{}", instance.borrow()),
+            Value::Native(object) => write!(f, "{}", object),
+            Value::Closure(closure) => write!(f, "{}", closure),
+            Value::BoundMethod(method) => write!(f, "{}", method),
+            Value::ObjClass(class) => write!(f, "{}", class),
+            Value::ObjInstance(instance) => write!(f, "{}", instance.borrow()),
+        }
+    }
+}
+
+impl Debug for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{:?}", s),
+            value => write!(f, "{}", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lexer::Literal;
+    use crate::symbol::Symbol;
+    use crate::value::Value;
+
+    #[test]
+    fn displays_values_like_lox() {
+        assert_eq!(Value::Nil.to_string(), "nil");
+        assert_eq!(Value::Boolean(true).to_string(), "true");
+        assert_eq!(Value::Number(3.0).to_string(), "3");
+        assert_eq!(Value::Number(-0.5).to_string(), "-0.5");
+        assert_eq!(Value::Number(f64::INFINITY).to_string(), "Infinity");
+        assert_eq!(Value::Number(f64::NEG_INFINITY).to_string(), "-Infinity");
+        assert_eq!(Value::Number(f64::NAN).to_string(), "NaN");
+        assert_eq!(Value::Number(1e21).to_string(), "1000000000000000000000");
+        assert_eq!(Value::String("hello".into()).to_string(), "hello");
+    }
+
+    #[test]
+    fn converts_literals() {
+        assert_eq!(Value::from(&Literal::Null), Value::Nil);
+        assert_eq!(Value::from(&Literal::Number(1.5)), Value::Number(1.5));
+        assert_eq!(Value::from(&Literal::String(Symbol::intern("a"))), Value::String("a".into()));
+    }
+
+    #[test]
+    fn compares_values() {
+        assert_eq!(Value::String("a".into()), Value::String("a".into()));
+        assert_ne!(Value::String("a".into()), Value::String("b".into()));
+        assert_ne!(Value::Number(0.0), Value::Boolean(false));
+        assert_ne!(Value::Nil, Value::Boolean(false));
+    }
+}
@@ -0,0 +1,688 @@
+use crate::callable::LoxCallable;
+use crate::chunk::{Constant, OpCode};
+use crate::diagnostic::{Diagnostic, Phase};
+use crate::disassembler::disassemble_instruction;
+use crate::gc::{GcConfig, Heap, Tracked};
+use crate::lox::MAX_CALL_DEPTH;
+use crate::native::builtins;
+use crate::object::{ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, Upvalue};
+use crate::output::Output;
+use crate::symbol::{Symbol, SymbolMap};
+use crate::value::Value;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The maximum number of call frames, where the top-level script takes one too.
+const FRAMES_MAX: usize = MAX_CALL_DEPTH + 1;
+
+/// Boxed to keep `VmResult` small on the happy path.
+pub type VmResult<T> = std::result::Result<T, Box<Diagnostic>>;
+
+/// A function call in progress.
+struct CallFrame {
+    closure: Rc<ObjClosure>,
+    /// The closure's function, kept here to save an indirection per instruction.
+    function: Rc<ObjFunction>,
+    /// The offset of the next instruction in the closure's chunk.
+    ip: usize,
+    /// The stack slot of the callee, followed by the arguments and then the other locals.
+    base: usize,
+}
+
+/// A stack-based virtual machine running functions compiled by the
+/// [`Compiler`](crate::compiler::Compiler).
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: SymbolMap<Value>,
+    /// Upvalues still pointing into the stack, ordered by slot.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// Where `print` statements write to.
+    output: Output,
+    /// Whether to print the stack and each instruction before executing it.
+    trace: bool,
+    /// The objects created while running, collected when only reachable from each other.
+    heap: Heap,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        let mut vm = Self {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: SymbolMap::default(),
+            open_upvalues: Vec::new(),
+            output: Output::stdout(),
+            trace: false,
+            heap: Heap::new(GcConfig::default()),
+        };
+        for native in builtins() {
+            let native = Rc::new(native);
+            vm.define_global(native.name(), Value::Callable(native.clone()));
+        }
+        vm
+    }
+
+    /// Sends the output of `print` statements to `output` from now on.
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    /// Prints the stack and the instruction about to run to the output, before each instruction.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Replaces the garbage collector's settings. Only objects allocated from now on are
+    /// collected.
+    pub fn set_gc_config(&mut self, config: GcConfig) {
+        self.heap = Heap::new(config);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.get(&Symbol::lookup(name)?).cloned()
+    }
+
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(Symbol::intern(name), value);
+    }
+
+    /// The names of all variables, functions and classes defined globally.
+    pub fn global_names(&self) -> Vec<String> {
+        self.globals.keys().map(|name| name.to_string()).collect()
+    }
+
+    /// Runs the top-level code of a script, returning the value it returns. After a runtime
+    /// error, the stack is reset so that the VM can run more code.
+    pub fn interpret(&mut self, script: Rc<ObjFunction>) -> VmResult<Value> {
+        self.collect_if_due();
+        self.heap.track_function(&script);
+        let closure = Rc::new(ObjClosure { function: script, upvalues: Vec::new() });
+        self.heap.track(Tracked::Closure(Rc::downgrade(&closure)));
+        self.stack.push(Value::Closure(closure.clone()));
+        self.call(closure, 0)?;
+        let result = self.run();
+        if result.is_err() {
+            // Closures that escaped the failed code keep the values they captured.
+            self.close_upvalues(0);
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    fn run(&mut self) -> VmResult<Value> {
+        loop {
+            if self.trace {
+                self.trace_instruction();
+            }
+            let mut byte = self.read_byte();
+            let wide = byte == OpCode::Wide as u8;
+            if wide {
+                byte = self.read_byte();
+            }
+            let op = OpCode::try_from(byte).expect("the compiler only emits valid opcodes");
+            match op {
+                OpCode::Constant => {
+                    let value = match self.read_constant(wide) {
+                        Constant::Number(n) => Value::Number(*n),
+                        Constant::String(s) => Value::String(s.clone()),
+                        Constant::Function(_) => unreachable!("functions are loaded as closures"),
+                    };
+                    self.stack.push(value);
+                },
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Boolean(true)),
+                OpCode::False => self.stack.push(Value::Boolean(false)),
+                OpCode::Pop => {
+                    self.pop();
+                },
+                OpCode::GetLocal => {
+                    let slot = self.frame().base + self.read_operand(wide);
+                    self.stack.push(self.stack[slot].clone());
+                },
+                OpCode::SetLocal => {
+                    let slot = self.frame().base + self.read_operand(wide);
+                    self.stack[slot] = self.peek(0).clone();
+                },
+                OpCode::GetGlobal => {
+                    let name = self.read_string(wide);
+                    match self.globals.get(&name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => return Err(self.undefined_variable(&name)),
+                    }
+                },
+                OpCode::DefineGlobal => {
+                    let name = self.read_string(wide);
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                },
+                OpCode::SetGlobal => {
+                    let name = self.read_string(wide);
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(&name) {
+                        Some(slot) => *slot = value,
+                        None => return Err(self.undefined_variable(&name)),
+                    }
+                },
+                OpCode::GetUpvalue => {
+                    let index = self.read_operand(wide);
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                },
+                OpCode::SetUpvalue => {
+                    let index = self.read_operand(wide);
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = self.peek(0).clone();
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                },
+                OpCode::GetProperty => {
+                    let name = self.read_string(wide);
+                    let Value::ObjInstance(instance) = self.peek(0).clone() else {
+                        return Err(self.error("Only instances have properties."));
+                    };
+                    // Fields shadow methods.
+                    let field = instance.borrow().fields.get(&name).cloned();
+                    if let Some(value) = field {
+                        self.pop();
+                        self.stack.push(value);
+                    } else {
+                        let class = instance.borrow().class.clone();
+                        self.bind_method(&class, &name)?;
+                    }
+                },
+                OpCode::SetProperty => {
+                    let name = self.read_string(wide);
+                    let Value::ObjInstance(instance) = self.peek(1).clone() else {
+                        return Err(self.error("Only instances have fields."));
+                    };
+                    let value = self.pop();
+                    instance.borrow_mut().fields.insert(name, value.clone());
+                    self.pop();
+                    self.stack.push(value);
+                },
+                OpCode::GetSuper => {
+                    let name = self.read_string(wide);
+                    // The compiler binds `super` to the superclass, but a compiled file could have
+                    // been tampered with, as for the other checks of `Inherit` and `Method`.
+                    let Value::ObjClass(superclass) = self.pop() else {
+                        return Err(self.error("Superclass must be a class."));
+                    };
+                    self.bind_method(&superclass, &name)?;
+                },
+                OpCode::Equal => {
+                    let (left, right) = self.pop_pair();
+                    self.stack.push(Value::Boolean(left == right));
+                },
+                OpCode::NotEqual => {
+                    let (left, right) = self.pop_pair();
+                    self.stack.push(Value::Boolean(left != right));
+                },
+                OpCode::Greater => self.compare(|left, right| left > right)?,
+                OpCode::GreaterEqual => self.compare(|left, right| left >= right)?,
+                OpCode::Less => self.compare(|left, right| left < right)?,
+                OpCode::LessEqual => self.compare(|left, right| left <= right)?,
+                OpCode::Add => {
+                    let value = match (self.peek(1), self.peek(0)) {
+                        (Value::Number(left), Value::Number(right)) => Value::Number(left + right),
+                        (Value::String(_), Value::String(_)) => self.concatenate(),
+                        _ => return Err(self.error("Operands must be two numbers or two strings.")),
+                    };
+                    self.pop_pair();
+                    self.stack.push(value);
+                },
+                OpCode::Subtract => self.arithmetic(|left, right| left - right)?,
+                OpCode::Multiply => self.arithmetic(|left, right| left * right)?,
+                OpCode::Divide => self.arithmetic(|left, right| left / right)?,
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Boolean(!value.is_truthy()));
+                },
+                OpCode::Negate => {
+                    let Value::Number(n) = self.peek(0) else {
+                        return Err(self.error("Operand must be a number."));
+                    };
+                    let value = Value::Number(-n);
+                    self.pop();
+                    self.stack.push(value);
+                },
+                OpCode::Print => {
+                    let value = self.pop();
+                    self.output.write_line(&value.to_string());
+                },
+                OpCode::Jump => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip += offset;
+                },
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_short();
+                    if !self.peek(0).is_truthy() {
+                        self.frame_mut().ip += offset;
+                    }
+                },
+                OpCode::Loop => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip -= offset;
+                },
+                OpCode::Call => {
+                    let argument_count = self.read_operand(wide);
+                    self.call_value(self.peek(argument_count).clone(), argument_count)?;
+                },
+                OpCode::Closure => {
+                    let Constant::Function(function) = self.read_constant(wide).clone() else {
+                        unreachable!("closures are always created from function constants")
+                    };
+                    // Captured upvalues are reachable from the open upvalues or the enclosing
+                    // closure until the new closure is on the stack.
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_operand(wide);
+                        let upvalue = if is_local {
+                            self.capture_upvalue(self.frame().base + index)
+                        } else {
+                            self.frame().closure.upvalues[index].clone()
+                        };
+                        upvalues.push(upvalue);
+                    }
+                    self.collect_if_due();
+                    let closure = Rc::new(ObjClosure { function, upvalues });
+                    self.heap.track(Tracked::Closure(Rc::downgrade(&closure)));
+                    self.stack.push(Value::Closure(closure));
+                },
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                },
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("returning from a function being called");
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    if self.frames.is_empty() {
+                        return Ok(result);
+                    }
+                    self.stack.push(result);
+                },
+                OpCode::Class => {
+                    let name = self.read_string(wide);
+                    self.collect_if_due();
+                    let class = Rc::new(ObjClass::new(&name));
+                    self.heap.track(Tracked::Class(Rc::downgrade(&class)));
+                    self.stack.push(Value::ObjClass(class));
+                },
+                OpCode::Inherit => {
+                    let Value::ObjClass(superclass) = self.peek(1) else {
+                        return Err(self.error("Superclass must be a class."));
+                    };
+                    let Value::ObjClass(subclass) = self.peek(0) else {
+                        return Err(self.error("Only classes can inherit."));
+                    };
+                    // Methods can't be added to a class later on, so copying them down
+                    // is equivalent to looking them up the superclass chain.
+                    let methods = superclass.methods.borrow().clone();
+                    subclass.methods.borrow_mut().extend(methods);
+                    self.pop();
+                },
+                OpCode::Method => {
+                    let name = self.read_string(wide);
+                    let Value::Closure(method) = self.pop() else {
+                        return Err(self.error("Methods must be functions."));
+                    };
+                    let Value::ObjClass(class) = self.peek(0) else {
+                        return Err(self.error("Only classes have methods."));
+                    };
+                    class.methods.borrow_mut().insert(name, method);
+                },
+                OpCode::Wide => {
+                    unreachable!("a prefix is read along with the instruction it widens")
+                },
+            }
+        }
+    }
+
+    fn trace_instruction(&self) {
+        let stack: String = self.stack.iter().map(|value| format!("[ {} ]", value)).collect();
+        self.output.write_line(&format!("          {}", stack));
+        let frame = self.frame();
+        let (instruction, _) = disassemble_instruction(&frame.closure.function.chunk, frame.ip);
+        self.output.write_line(&instruction);
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("code only runs inside a call frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("code only runs inside a call frame")
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_short(&mut self) -> usize {
+        u16::from_be_bytes([self.read_byte(), self.read_byte()]) as usize
+    }
+
+    /// Reads an index operand, three bytes long after an [`OpCode::Wide`] prefix.
+    fn read_operand(&mut self, wide: bool) -> usize {
+        if wide {
+            u32::from_be_bytes([0, self.read_byte(), self.read_byte(), self.read_byte()]) as usize
+        } else {
+            self.read_byte() as usize
+        }
+    }
+
+    fn read_constant(&mut self, wide: bool) -> &Constant {
+        let index = self.read_operand(wide);
+        &self.frame().function.chunk.constants[index]
+    }
+
+    fn read_string(&mut self, wide: bool) -> Symbol {
+        match self.read_constant(wide) {
+            Constant::String(s) => s.clone(),
+            _ => unreachable!("names are always string constants"),
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the compiler keeps the stack balanced")
+    }
+
+    /// Pops the two operands of a binary operator.
+    fn pop_pair(&mut self) -> (Value, Value) {
+        let right = self.pop();
+        (self.pop(), right)
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn numbers(&self) -> VmResult<(f64, f64)> {
+        match (self.peek(1), self.peek(0)) {
+            (Value::Number(left), Value::Number(right)) => Ok((*left, *right)),
+            _ => Err(self.error("Operands must be numbers.")),
+        }
+    }
+
+    fn arithmetic(&mut self, operator: fn(f64, f64) -> f64) -> VmResult<()> {
+        let (left, right) = self.numbers()?;
+        self.pop_pair();
+        self.stack.push(Value::Number(operator(left, right)));
+        Ok(())
+    }
+
+    fn compare(&mut self, operator: fn(&f64, &f64) -> bool) -> VmResult<()> {
+        let (left, right) = self.numbers()?;
+        self.pop_pair();
+        self.stack.push(Value::Boolean(operator(&left, &right)));
+        Ok(())
+    }
+
+    /// Calls `callee`, which sits on the stack below its arguments.
+    fn call_value(&mut self, callee: Value, argument_count: usize) -> VmResult<()> {
+        let callee_slot = self.stack.len() - argument_count - 1;
+        match callee {
+            Value::Closure(closure) => self.call(closure, argument_count),
+            Value::BoundMethod(bound) => {
+                self.stack[callee_slot] = bound.receiver.clone();
+                self.call(bound.method.clone(), argument_count)
+            },
+            Value::ObjClass(class) => {
+                self.collect_if_due();
+                let instance = Rc::new(RefCell::new(ObjInstance::new(class.clone())));
+                self.heap.track(Tracked::Instance(Rc::downgrade(&instance)));
+                self.stack[callee_slot] = Value::ObjInstance(instance);
+                match class.find_method(&Symbol::init()) {
+                    Some(initializer) => self.call(initializer, argument_count),
+                    None => self.check_arity(0, argument_count),
+                }
+            },
+            Value::Callable(callable) if callable.as_native().is_some() => {
+                let native = callable.as_native().expect("checked by the guard");
+                self.check_arity(native.arity(), argument_count)?;
+                let result = native.apply(&self.stack[callee_slot + 1..]);
+                let value = result.map_err(|message| self.error(&message))?;
+                self.stack.truncate(callee_slot);
+                self.stack.push(value);
+                Ok(())
+            },
+            _ => Err(self.error("Can only call functions and classes.")),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<ObjClosure>, argument_count: usize) -> VmResult<()> {
+        self.check_arity(closure.function.arity, argument_count)?;
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.error("Stack overflow."));
+        }
+        let base = self.stack.len() - argument_count - 1;
+        let function = closure.function.clone();
+        self.frames.push(CallFrame { closure, function, ip: 0, base });
+        Ok(())
+    }
+
+    fn check_arity(&self, arity: usize, argument_count: usize) -> VmResult<()> {
+        if arity != argument_count {
+            let message = format!("Expected {} arguments but got {}.", arity, argument_count);
+            return Err(self.error(&message));
+        }
+        Ok(())
+    }
+
+    /// Replaces the instance on top of the stack with its method `name`, bound to it.
+    fn bind_method(&mut self, class: &ObjClass, name: &Symbol) -> VmResult<()> {
+        let Some(method) = class.find_method(name) else {
+            return Err(self.error(&format!("Undefined property '{}'.", name)));
+        };
+        // The receiver stays on the stack until the bound method replaces it, so that a
+        // collection can't free it.
+        self.collect_if_due();
+        let bound = Rc::new(ObjBoundMethod { receiver: self.peek(0).clone(), method });
+        self.heap.track(Tracked::BoundMethod(Rc::downgrade(&bound)));
+        self.pop();
+        self.stack.push(Value::BoundMethod(bound));
+        Ok(())
+    }
+
+    /// Returns the upvalue for the variable in stack slot `slot`, sharing it with the closures
+    /// that already captured it.
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let position = self
+            .open_upvalues
+            .iter()
+            .rposition(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open <= slot));
+        let insert_at = match position {
+            Some(index) => {
+                if matches!(*self.open_upvalues[index].borrow(), Upvalue::Open(open) if open == slot)
+                {
+                    return self.open_upvalues[index].clone();
+                }
+                index + 1
+            },
+            None => 0,
+        };
+        self.collect_if_due();
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.heap.track(Tracked::Upvalue(Rc::downgrade(&upvalue)));
+        self.open_upvalues.insert(insert_at, upvalue.clone());
+        upvalue
+    }
+
+    /// Moves the variables in stack slot `from` and above into the upvalues capturing them.
+    fn close_upvalues(&mut self, from: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) if slot >= from => slot,
+                _ => break,
+            };
+            *upvalue.borrow_mut() = Upvalue::Closed(self.stack[slot].clone());
+            self.open_upvalues.pop();
+        }
+    }
+
+    /// Concatenates the two strings on top of the stack, leaving them there.
+    fn concatenate(&mut self) -> Value {
+        self.collect_if_due();
+        let (Value::String(left), Value::String(right)) = (self.peek(1), self.peek(0)) else {
+            unreachable!("only called with two strings on top of the stack")
+        };
+        let result = Symbol::intern(&[&**left, &**right].concat());
+        // Only the interner and `result` refer to a string that was just allocated; an equal
+        // string that already existed is tracked already, or is a constant.
+        if Rc::strong_count(result.as_rc()) == 2 {
+            self.heap.track(Tracked::String(Rc::downgrade(result.as_rc())));
+        }
+        Value::String(result)
+    }
+
+    /// Runs a garbage collection if one is due. Called before allocating an object, so
+    /// everything the object will reference must be reachable from the roots: the stack, the
+    /// call frames, the open upvalues and the global variables.
+    fn collect_if_due(&mut self) {
+        if !self.heap.should_collect() {
+            return;
+        }
+        for value in self.stack.iter().chain(self.globals.values()) {
+            self.heap.mark_value(value);
+        }
+        for frame in &self.frames {
+            self.heap.mark_closure(&frame.closure);
+        }
+        for upvalue in &self.open_upvalues {
+            self.heap.mark_upvalue(upvalue);
+        }
+        self.heap.collect();
+    }
+
+    fn undefined_variable(&self, name: &str) -> Box<Diagnostic> {
+        self.error(&format!("Undefined variable '{}'.", name))
+    }
+
+    /// A runtime error at the instruction being executed.
+    fn error(&self, message: &str) -> Box<Diagnostic> {
+        let frame = self.frame();
+        let chunk = &frame.closure.function.chunk;
+        let offset = frame.ip.saturating_sub(1);
+        let (line, span) = (chunk.lines[offset], chunk.spans[offset]);
+        Box::new(Diagnostic::error(Phase::Runtime, "E0300", line, span, message))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gc::GcConfig;
+    use crate::test_support;
+    use crate::vm::Vm;
+
+    /// Runs `source` in a fresh VM, returning what it printed and the runtime error, if any.
+    fn run(source: &str) -> (String, Option<String>) {
+        test_support::run(&mut Vm::new(), source)
+    }
+
+    #[test]
+    fn traces_execution() {
+        let mut vm = Vm::new();
+        vm.set_trace(true);
+        let (output, error) = test_support::run(&mut vm, "print 1 + 2;");
+        assert_eq!(error, None);
+        assert_eq!(
+            output,
+            [
+                "          [ <script> ]",
+                "0000    1 OP_CONSTANT         0 '1'",
+                "          [ <script> ][ 1 ]",
+                "0002    | OP_CONSTANT         1 '2'",
+                "          [ <script> ][ 1 ][ 2 ]",
+                "0004    | OP_ADD",
+                "          [ <script> ][ 3 ]",
+                "0005    | OP_PRINT",
+                "3",
+                "          [ <script> ]",
+                "0006    | OP_NIL",
+                "          [ <script> ][ nil ]",
+                "0007    | OP_RETURN",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn collects_unreachable_cycles() {
+        let source =
+            "class Node {}\nfun link() {\n  var node = Node();\n  fun get() { return node; }\n  \
+                      node.get = get;\n  return node;\n}\nvar kept = link();\n\
+                      for (var i = 0; i < 100; i = i + 1) link();\nprint kept.get() == kept;";
+        let mut vm = Vm::new();
+        vm.set_gc_config(GcConfig { stress: true, ..GcConfig::default() });
+        assert_eq!(test_support::run(&mut vm, source), ("true\n".to_string(), None));
+        // Each call to `link` leaves behind a node, the closure and its upvalue.
+        assert!(vm.heap.len() < 20, "{} objects survived", vm.heap.len());
+    }
+
+    #[test]
+    fn runs_arithmetic_and_control_flow() {
+        let source =
+            "var sum = 0;\nfor (var i = 1; i <= 4; i = i + 1) if (i != 3) sum = sum + i;\n\
+                      print sum;\nprint !(sum > 5) or \"x\";\nprint nil and 1;";
+        assert_eq!(run(source), ("7\nx\nnil\n".to_string(), None));
+    }
+
+    #[test]
+    fn captures_variables_in_closures() {
+        let source = "fun counter() {\n  var i = 0;\n  fun next() { i = i + 1; return i; }\n  \
+                      return next;\n}\nvar a = counter();\nvar b = counter();\n\
+                      print a(); print a(); print b(); print a;";
+        assert_eq!(run(source), ("1\n2\n1\n<fn next>\n".to_string(), None));
+
+        let source = "var get; var set;\n{ var x = 1; fun g() { return x; } fun s(v) { x = v; }\n\
+                      get = g; set = s; }\nset(5); print get();";
+        assert_eq!(run(source), ("5\n".to_string(), None));
+    }
+
+    #[test]
+    fn runs_classes_with_inheritance() {
+        let source = "class A {\n  init(name) { this.name = name; }\n  greet() { return \"A \" + \
+                      this.name; }\n}\nclass B < A {\n  greet() { return super.greet() + \"!\"; }\n}\n\
+                      var b = B(\"b\");\nprint b.greet();\nprint b;\nprint B;\nprint b.init(\"c\").name;";
+        assert_eq!(run(source), ("A b!\nB instance\nB\nc\n".to_string(), None));
+    }
+
+    #[test]
+    fn reports_runtime_errors_at_their_line() {
+        assert_eq!(
+            run("print 1;\nprint -\"a\";"),
+            ("1\n".to_string(), Some("Operand must be a number.\n[line 2]".to_string()))
+        );
+        assert_eq!(
+            run("fun f(a) {}\nf(\n1, 2);").1.as_deref(),
+            Some("Expected 1 arguments but got 2.\n[line 3]")
+        );
+        assert_eq!(run("fun f() { f(); }\nf();").1.as_deref(), Some("Stack overflow.\n[line 1]"));
+        assert_eq!(
+            run("var a = 1;\nclass B < a {}").1.as_deref(),
+            Some("Superclass must be a class.\n[line 2]")
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Runs every `tests/lox/**/*.lox` program and checks it against the annotations in its comments,
+//! in the format of the Crafting Interpreters test suite:
+//!
+//! - `// expect: <text>`, a line printed by the program,
+//! - `// expect runtime error: <message>`, the runtime error that stops it on this line,
+//! - `// Error at 'x': <message>`, a compile error on this line,
+//! - `// [line N] Error at 'x': <message>`, a compile error on line `N`. The `[java line N]`
+//!   variant is accepted too, and `[c line N]` ones, meant for clox only, are ignored.
+
+use codecrafters_interpreter::lox::{Backend, Config, GcConfig, Lox, LoxError};
+use codecrafters_interpreter::output::Buffer;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const EXPECT: &str = "// expect: ";
+const EXPECT_RUNTIME_ERROR: &str = "// expect runtime error: ";
+
+/// What a test program should print and fail with.
+#[derive(Debug, Default, PartialEq)]
+struct Expectations {
+    output: Vec<String>,
+    /// Compile errors, rendered as `[line N] Error...`.
+    errors: Vec<String>,
+    /// The message and line of the runtime error.
+    runtime_error: Option<(String, usize)>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Self {
+        let mut expectations = Self::default();
+        for (index, line) in source.lines().enumerate() {
+            let number = index + 1;
+            if let Some(output) = find(line, EXPECT) {
+                expectations.output.push(output.to_string());
+            } else if let Some(message) = find(line, EXPECT_RUNTIME_ERROR) {
+                expectations.runtime_error = Some((message.to_string(), number));
+            } else if let Some(error) = find(line, "// Error") {
+                expectations.errors.push(format!("[line {number}] Error{error}"));
+            } else if let Some(error) =
+                find(line, "// [line ").or_else(|| find(line, "// [java line "))
+            {
+                expectations.errors.push(format!("[line {error}"));
+            }
+        }
+        expectations
+    }
+
+    /// What running `source` on `backend` actually did, in the same shape. The VM collects
+    /// garbage before every allocation, to catch objects it forgets to root.
+    fn run(source: &str, backend: Backend) -> Self {
+        let output = Buffer::new();
+        let config = Config {
+            backend,
+            gc: GcConfig { stress: true, ..GcConfig::default() },
+            output: output.clone().into(),
+            errors: Buffer::new().into(),
+            ..Config::default()
+        };
+        let mut expectations = Self::default();
+        match Lox::with_config(config).run(source) {
+            Ok(()) => {},
+            Err(LoxError::Compile(diagnostics)) => {
+                expectations.errors = diagnostics.iter().map(|d| d.render_plain()).collect();
+            },
+            Err(LoxError::Runtime(diagnostic)) => {
+                expectations.runtime_error = Some((diagnostic.message, diagnostic.line));
+            },
+            Err(LoxError::Bytecode(error)) => {
+                unreachable!("source is never loaded as bytecode: {error}")
+            },
+        }
+        expectations.output = output.take().lines().map(String::from).collect();
+        expectations
+    }
+}
+
+/// The rest of `line` after `marker`, if it contains it.
+fn find<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    line.find(marker).map(|index| &line[index + marker.len()..])
+}
+
+fn lox_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            lox_files(&path, files);
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            files.push(path);
+        }
+    }
+}
+
+#[test]
+fn lox_programs_match_expectations() {
+    // The tree-walking interpreter needs a deep native stack to reach the call depth limit.
+    let thread = std::thread::Builder::new().stack_size(64 * 1024 * 1024);
+    thread.spawn(run_lox_programs).unwrap().join().unwrap();
+}
+
+fn run_lox_programs() {
+    let mut files = Vec::new();
+    lox_files(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox"), &mut files);
+    files.sort();
+    assert!(!files.is_empty(), "no .lox files found");
+
+    let mut failures = Vec::new();
+    for file in &files {
+        let source = fs::read_to_string(file).unwrap();
+        let expected = Expectations::parse(&source);
+        for backend in [Backend::Interpreter, Backend::Vm] {
+            let actual = Expectations::run(&source, backend);
+            if actual != expected {
+                failures.push(format!(
+                    "{} ({:?})\n  expected: {:?}\n  actual:   {:?}",
+                    file.display(),
+                    backend,
+                    expected,
+                    actual
+                ));
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} of {} runs failed:\n{}",
+        failures.len(),
+        files.len() * 2,
+        failures.join("\n")
+    );
+}